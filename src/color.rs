@@ -0,0 +1,77 @@
+use crate::stepn_config::StepnConfig;
+use colored::Color;
+use std::io::IsTerminal;
+
+/// Label colours handed out to services without a `color`. Red and yellow are
+/// left out because stepn uses them for stderr and its own messages.
+const PALETTE: &[Color] = &[
+    Color::Green,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Blue,
+    Color::BrightGreen,
+    Color::BrightCyan,
+    Color::BrightMagenta,
+    Color::BrightBlue,
+];
+
+/// Parses a colour name such as `cyan` or `bright_blue`.
+pub fn parse_color(name: &str) -> Result<Color, String> {
+    name.replace(['_', '-'], " ").parse().map_err(|_| {
+        format!(
+            "unknown color \"{}\" (expected black, red, green, yellow, blue, magenta, cyan, white, or one of them prefixed with bright_)",
+            name
+        )
+    })
+}
+
+/// The label colour of a service: its configured `color`, or the next palette
+/// entry in service name order, so up to `PALETTE.len()` services never share
+/// one and each keeps its colour between runs of the same config.
+pub fn service_color(config: &StepnConfig, name: &str) -> Color {
+    let configured = config.services.get(name).and_then(|s| s.color.as_deref());
+    if let Some(color) = configured.and_then(|c| parse_color(c).ok()) {
+        return color;
+    }
+    let index = config
+        .services
+        .keys()
+        .filter(|other| other.as_str() < name)
+        .count();
+    PALETTE[index % PALETTE.len()]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => Err(format!(
+                "unknown color choice \"{}\" (expected auto, always or never)",
+                other
+            )),
+        }
+    }
+
+    /// Turns colour output on or off for the whole process. `auto` colours only
+    /// when stdout is a terminal and `NO_COLOR` is unset or empty.
+    pub fn apply(self) {
+        let enabled = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        };
+        colored::control::set_override(enabled);
+    }
+}
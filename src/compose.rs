@@ -0,0 +1,367 @@
+use crate::stepn_config::{HealthChecker, Service, StepnConfig};
+use anyhow::{bail, Context, Result};
+use serde_norway::{Mapping, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Keys that are fully handled by the conversion itself.
+const HANDLED_KEYS: &[&str] = &[
+    "command",
+    "depends_on",
+    "environment",
+    "env_file",
+    "healthcheck",
+    "restart",
+];
+
+/// Keys that only matter to docker itself.
+const CONTAINER_KEYS: &[&str] = &[
+    "image",
+    "build",
+    "ports",
+    "volumes",
+    "networks",
+    "container_name",
+    "working_dir",
+    "entrypoint",
+    "user",
+    "expose",
+];
+
+pub struct ComposeImport {
+    pub config: StepnConfig,
+    pub warnings: Vec<String>,
+}
+
+/// Converts a docker-compose file into a stepn config.
+///
+/// By default every service runs through `docker compose run`, so container
+/// settings stay with compose. With `raw`, the service's own `command` is run
+/// on the host instead and container-only settings are reported as dropped.
+pub fn import_compose(filepath: &str, raw: bool) -> Result<ComposeImport> {
+    let content =
+        std::fs::read_to_string(filepath).with_context(|| format!("{} not found", filepath))?;
+    let doc: Value = serde_norway::from_str(&content)
+        .with_context(|| format!("failed to parse {}", filepath))?;
+    let Some(compose_services) = doc.get("services").and_then(Value::as_mapping) else {
+        bail!("{} has no `services` section", filepath);
+    };
+
+    let base_dir = Path::new(filepath).parent().unwrap_or(Path::new(""));
+    let mut warnings = Vec::new();
+    let mut services = HashMap::new();
+
+    for (name, def) in compose_services {
+        let Some(name) = name.as_str() else {
+            bail!("service names must be strings");
+        };
+        let empty = Mapping::new();
+        let def = def.as_mapping().unwrap_or(&empty);
+        let mut warn = |msg: String| warnings.push(format!("{}: {}", name, msg));
+
+        let depends_on = def
+            .get("depends_on")
+            .map(|deps| convert_depends_on(deps, compose_services, &mut warn));
+
+        // `docker compose run` applies the service's own definition, so
+        // only a service run on the host needs its environment converted.
+        let host_command = raw
+            .then(|| def.get("command").and_then(command_string))
+            .flatten();
+        let on_host = host_command.is_some();
+        let command = host_command.unwrap_or_else(|| {
+            if raw {
+                warn("no `command` to run on the host, using `docker compose run`".into());
+            }
+            docker_compose_run(filepath, name)
+        });
+
+        let mut environments = HashMap::new();
+        if on_host {
+            if let Some(env_file) = def.get("env_file") {
+                for file in string_or_list(env_file) {
+                    let path = base_dir.join(&file);
+                    match std::fs::read_to_string(&path) {
+                        Ok(content) => environments.extend(parse_env_file(&content)),
+                        Err(e) => warn(format!("env_file '{}' skipped: {}", path.display(), e)),
+                    }
+                }
+            }
+            if let Some(environment) = def.get("environment") {
+                environments.extend(convert_environment(environment));
+            }
+            let mut interpolated: Vec<_> = environments
+                .iter()
+                .filter(|(_, v)| v.contains("${"))
+                .map(|(k, _)| k.as_str())
+                .collect();
+            interpolated.sort();
+            for key in interpolated {
+                warn(format!(
+                    "environment '{}' uses `${{...}}`, which stepn passes through unexpanded",
+                    key
+                ));
+            }
+        }
+
+        let health_checker = def
+            .get("healthcheck")
+            .and_then(|hc| convert_healthcheck(hc, &mut warn));
+
+        let (restart, max_restarts) = match def.get("restart").and_then(Value::as_str) {
+            None | Some("no") => (false, None),
+            Some("always") | Some("unless-stopped") => (true, Some(0)),
+            Some("on-failure") => (true, None),
+            Some(policy) => match policy
+                .strip_prefix("on-failure:")
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(n) => (true, Some(n)),
+                None => {
+                    warn(format!("unknown restart policy '{}' ignored", policy));
+                    (false, None)
+                }
+            },
+        };
+
+        // Under `docker compose run` compose still applies everything else in
+        // the definition, so only services run on the host can lose settings.
+        if on_host {
+            for key in def.keys().filter_map(Value::as_str) {
+                if HANDLED_KEYS.contains(&key) {
+                    continue;
+                }
+                if CONTAINER_KEYS.contains(&key) {
+                    warn(format!(
+                        "`{}` only applies to containers and was dropped",
+                        key
+                    ));
+                } else {
+                    warn(format!("`{}` has no stepn equivalent and was dropped", key));
+                }
+            }
+        }
+
+        services.insert(
+            name.to_string(),
+            Service {
+                command,
+                depends_on,
+                environments: (!environments.is_empty()).then_some(environments),
+                health_checker,
+                restart,
+                max_restarts,
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok(ComposeImport {
+        config: StepnConfig {
+            services,
+            ..Default::default()
+        },
+        warnings,
+    })
+}
+
+fn docker_compose_run(filepath: &str, name: &str) -> String {
+    format!(
+        "docker compose -f {} run --rm --no-deps --service-ports {}",
+        shell_quote(filepath),
+        shell_quote(name)
+    )
+}
+
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@".contains(c))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// `command` and `healthcheck.test` may be a string or an exec-form list.
+fn command_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Sequence(parts) => {
+            let parts: Vec<&str> = parts.iter().filter_map(Value::as_str).collect();
+            let parts = match parts.first() {
+                Some(&"CMD-SHELL") => return Some(parts[1..].join(" ")),
+                Some(&"CMD") => &parts[1..],
+                Some(&"NONE") => return None,
+                _ => &parts[..],
+            };
+            Some(
+                parts
+                    .iter()
+                    .map(|p| shell_quote(p))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn string_or_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Sequence(items) => items
+            .iter()
+            .filter_map(|item| {
+                item.as_str()
+                    .or_else(|| item.get("path").and_then(Value::as_str))
+                    .map(str::to_string)
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Maps a compose `healthcheck` to a probe command. Only the interval carries
+/// over; stepn keeps probing until the check passes. The probe runs on the
+/// host, which reaches the container through its published ports.
+fn convert_healthcheck(hc: &Value, warn: &mut impl FnMut(String)) -> Option<HealthChecker> {
+    if hc.get("disable").and_then(Value::as_bool).unwrap_or(false) {
+        return None;
+    }
+    let command = hc.get("test").and_then(command_string)?;
+    let interval_sec = hc
+        .get("interval")
+        .and_then(Value::as_str)
+        .and_then(|interval| {
+            let secs = compose_duration_secs(interval);
+            if secs.is_none() {
+                warn(format!("healthcheck interval '{}' ignored", interval));
+            }
+            secs
+        });
+    Some(HealthChecker {
+        output_trigger: None,
+        command: Some(command),
+        interval_sec,
+    })
+}
+
+/// Parses compose durations such as `30s` or `1m30s`, rounded up to whole
+/// seconds.
+fn compose_duration_secs(s: &str) -> Option<u64> {
+    let mut millis: u64 = 0;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => 1,
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        millis = millis.checked_add(n.checked_mul(unit)?)?;
+    }
+    Some(millis.div_ceil(1_000).max(1))
+}
+
+fn convert_depends_on(
+    deps: &Value,
+    compose_services: &Mapping,
+    warn: &mut impl FnMut(String),
+) -> Vec<String> {
+    match deps {
+        Value::Sequence(items) => items
+            .iter()
+            .filter_map(|d| d.as_str().map(str::to_string))
+            .collect(),
+        Value::Mapping(map) => map
+            .iter()
+            .filter_map(|(dep, opts)| {
+                let dep = dep.as_str()?;
+                match opts.get("condition").and_then(Value::as_str) {
+                    Some("service_completed_successfully") => warn(format!(
+                        "depends_on '{}' waits for completion in compose; stepn waits for readiness instead",
+                        dep
+                    )),
+                    Some("service_healthy") => {
+                        let has_healthcheck = compose_services
+                            .get(dep)
+                            .and_then(|d| d.get("healthcheck"))
+                            .is_some_and(|hc| {
+                                !hc.get("disable").and_then(Value::as_bool).unwrap_or(false)
+                                    && hc.get("test").and_then(command_string).is_some()
+                            });
+                        if !has_healthcheck {
+                            warn(format!(
+                                "depends_on '{}' waits for it to be healthy, but it has no healthcheck; stepn waits for its first output line",
+                                dep
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+                Some(dep.to_string())
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `environment` may be a mapping or a list of `KEY=VALUE` entries. Bare keys
+/// pass the host value through, which stepn children inherit anyway.
+fn convert_environment(value: &Value) -> HashMap<String, String> {
+    match value {
+        Value::Mapping(map) => map
+            .iter()
+            .filter_map(|(k, v)| {
+                let k = k.as_str()?;
+                let v = match v {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    _ => return None,
+                };
+                Some((k.to_string(), v))
+            })
+            .collect(),
+        Value::Sequence(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+fn parse_env_file(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(v);
+            (k.trim().to_string(), v.to_string())
+        })
+        .collect()
+}
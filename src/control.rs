@@ -0,0 +1,233 @@
+use crate::history::RecordedLine;
+use crate::status::ServiceStatus;
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, Local};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Runtime state of a running stack, next to the config file.
+pub const STATE_DIR: &str = ".stepn";
+
+#[derive(Clone, Debug)]
+pub struct ControlPaths {
+    pub dir: PathBuf,
+    pub pidfile: PathBuf,
+    pub socket: PathBuf,
+    /// Output of a supervisor started with `up -d`.
+    pub log: PathBuf,
+}
+
+impl ControlPaths {
+    pub fn for_config(config_path: &str) -> Self {
+        let base = Path::new(config_path)
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let dir = base.join(STATE_DIR);
+        ControlPaths {
+            pidfile: dir.join("stepn.pid"),
+            socket: dir.join("control.sock"),
+            log: dir.join("stepn.log"),
+            dir,
+        }
+    }
+}
+
+/// PID of the supervisor recorded in the pidfile, if that process is alive.
+pub fn running_pid(paths: &ControlPaths) -> Option<u32> {
+    let pid: i32 = std::fs::read_to_string(&paths.pidfile)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).ok()?;
+    Some(pid as u32)
+}
+
+/// The pidfile, locked for as long as this process owns the state directory.
+static PIDFILE_LOCK: Mutex<Option<Flock<File>>> = Mutex::new(None);
+
+/// Claims the control socket for this process and writes the pidfile. The
+/// pidfile is locked first, so two supervisors starting at once can't both
+/// take over the socket.
+pub fn bind(paths: &ControlPaths) -> Result<UnixListener> {
+    std::fs::create_dir_all(&paths.dir)
+        .with_context(|| format!("cannot create {}", paths.dir.display()))?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&paths.pidfile)
+        .with_context(|| format!("cannot open {}", paths.pidfile.display()))?;
+    let mut pidfile = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+        Ok(locked) => locked,
+        Err((_, Errno::EWOULDBLOCK)) => match running_pid(paths) {
+            Some(pid) => bail!("stepn is already running here (pid {})", pid),
+            None => bail!("stepn is already starting here"),
+        },
+        Err((_, e)) => {
+            bail!("cannot lock {}: {}", paths.pidfile.display(), e)
+        }
+    };
+    // Left behind by a supervisor that did not shut down cleanly.
+    let _ = std::fs::remove_file(&paths.socket);
+    let listener = UnixListener::bind(&paths.socket)
+        .with_context(|| format!("cannot bind {}", paths.socket.display()))?;
+    pidfile
+        .set_len(0)
+        .and_then(|()| writeln!(*pidfile, "{}", std::process::id()))
+        .with_context(|| format!("cannot write {}", paths.pidfile.display()))?;
+    *PIDFILE_LOCK.lock().expect("lock poisoned") = Some(pidfile);
+    Ok(listener)
+}
+
+/// Removes the socket and pidfile if they belong to this process, and the
+/// state directory if nothing else is left in it.
+pub fn cleanup(paths: &ControlPaths) {
+    let ours = std::fs::read_to_string(&paths.pidfile)
+        .map(|s| s.trim() == std::process::id().to_string())
+        .unwrap_or(false);
+    if ours {
+        let _ = std::fs::remove_file(&paths.socket);
+        let _ = std::fs::remove_file(&paths.pidfile);
+        let _ = std::fs::remove_dir(&paths.dir);
+    }
+    PIDFILE_LOCK.lock().expect("lock poisoned").take();
+}
+
+/// One request per connection, sent as a single JSON line.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    /// Stop every service and exit, keeping the state directory.
+    Stop,
+    /// Like `Stop`, and also remove the state directory.
+    Down,
+    /// Start services that were stopped or have exited.
+    StartServices {
+        services: Vec<String>,
+    },
+    /// Stop services while the rest of the stack keeps running.
+    StopServices {
+        services: Vec<String>,
+        cascade: bool,
+    },
+    RestartServices {
+        services: Vec<String>,
+        cascade: bool,
+    },
+    /// Recent output of `services` (all if empty): the last `tail` lines of
+    /// each, only those written at or after `since`, and with `follow`, every
+    /// line after that until the client disconnects.
+    Logs {
+        services: Vec<String>,
+        tail: Option<usize>,
+        since: Option<DateTime<Local>>,
+        follow: bool,
+    },
+}
+
+/// Replies are JSON lines; the server closes the connection when done.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Ok {
+        message: String,
+    },
+    Status {
+        services: BTreeMap<String, ServiceStatus>,
+    },
+    Error {
+        message: String,
+    },
+    /// Sent once before the lines of a `Logs` request.
+    Logs {
+        services: Vec<String>,
+        /// When the supervisor started, for `{elapsed}` in prefixes.
+        started: DateTime<Local>,
+    },
+    Line {
+        service: String,
+        line: RecordedLine,
+    },
+    /// Lines a following client missed because it fell behind.
+    Skipped {
+        lines: u64,
+    },
+}
+
+/// The server's end of one connection.
+pub struct Connection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    pub fn new(stream: UnixStream) -> Self {
+        let (read, writer) = stream.into_split();
+        Connection {
+            lines: BufReader::new(read).lines(),
+            writer,
+        }
+    }
+
+    pub async fn request(&mut self) -> Result<Request> {
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .context("connection closed before a request was sent")?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    pub async fn send(&mut self, response: &Response) -> Result<()> {
+        let mut line = serde_json::to_string(response)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+/// The client's end of one connection.
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+}
+
+impl Client {
+    pub async fn connect(paths: &ControlPaths, request: &Request) -> Result<Self> {
+        if running_pid(paths).is_none() {
+            bail!(
+                "stepn is not running here (no live pid in {})",
+                paths.pidfile.display()
+            );
+        }
+        let stream = UnixStream::connect(&paths.socket)
+            .await
+            .with_context(|| format!("cannot connect to {}", paths.socket.display()))?;
+        let (read, mut write) = stream.into_split();
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        write.write_all(line.as_bytes()).await?;
+        Ok(Client {
+            lines: BufReader::new(read).lines(),
+        })
+    }
+
+    pub async fn recv(&mut self) -> Result<Option<Response>> {
+        match self.lines.next_line().await? {
+            Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use crate::stepn_config::ConfigFormat;
+use colored::Colorize;
+use std::fmt;
+use std::ops::Range;
+use toml_edit::{ImDocument, Item};
+use yaml_rust2::parser::{Event as YamlEvent, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Formats a key path the way it would be written in TOML, e.g. `services.web.depends_on[1]`.
+pub fn display_path(path: &[PathSegment]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(key);
+            }
+            PathSegment::Index(i) => out.push_str(&format!("[{}]", i)),
+        }
+    }
+    out
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single config problem, optionally pointing at the offending bytes of the source.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Range<usize>>,
+    /// Key path of the offending value, shown when there is no span to highlight.
+    pub path: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            path: None,
+            help: None,
+        }
+    }
+
+    fn render(&self, f: &mut fmt::Formatter<'_>, filepath: &str, content: &str) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        writeln!(f, "{}: {}", label, self.message.bold())?;
+
+        let span = self.span.clone().filter(|s| s.start <= content.len());
+        let Some(span) = span else {
+            match &self.path {
+                Some(path) => writeln!(f, "  {} {} ({})", "-->".blue().bold(), filepath, path)?,
+                None => writeln!(f, "  {} {}", "-->".blue().bold(), filepath)?,
+            }
+            if let Some(help) = &self.help {
+                writeln!(f, "   {} help: {}", "=".blue().bold(), help)?;
+            }
+            return Ok(());
+        };
+
+        let before = &content[..span.start];
+        let line_no = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = content[line_start..]
+            .find('\n')
+            .map(|i| line_start + i)
+            .unwrap_or(content.len());
+        let line = content[line_start..line_end].trim_end_matches('\r');
+        let column = content[line_start..span.start].chars().count() + 1;
+        let width = content[span.start..span.end.min(line_end).max(span.start)]
+            .chars()
+            .count()
+            .max(1);
+
+        let gutter = " ".repeat(line_no.to_string().len());
+        let bar = "|".blue().bold();
+        writeln!(
+            f,
+            "{}{} {}:{}:{}",
+            gutter,
+            "-->".blue().bold(),
+            filepath,
+            line_no,
+            column
+        )?;
+        writeln!(f, "{} {}", gutter, bar)?;
+        writeln!(f, "{} {} {}", line_no.to_string().blue().bold(), bar, line)?;
+        writeln!(
+            f,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            " ".repeat(column - 1),
+            match self.severity {
+                Severity::Error => "^".repeat(width).red().bold(),
+                Severity::Warning => "^".repeat(width).yellow().bold(),
+            }
+        )?;
+        if let Some(help) = &self.help {
+            writeln!(f, "{} {} help: {}", gutter, "=".blue().bold(), help)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(help) = &self.help {
+            write!(f, "\nhelp: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Every diagnostic found in one config file, rendered rustc-style by `Display`.
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub filepath: String,
+    pub content: String,
+    pub items: Vec<Diagnostic>,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            item.render(f, &self.filepath, &self.content)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Finds the source span of the value at `path`, or of the deepest key on
+/// the way to it that exists.
+pub fn locate(content: &str, format: ConfigFormat, path: &[PathSegment]) -> Option<Range<usize>> {
+    match format {
+        ConfigFormat::Toml => locate_toml(content, path),
+        // JSON is valid YAML, so one event parser covers both.
+        ConfigFormat::Yaml | ConfigFormat::Json => locate_yaml(content, path),
+        ConfigFormat::Procfile => None,
+    }
+}
+
+fn locate_toml(content: &str, path: &[PathSegment]) -> Option<Range<usize>> {
+    let doc = ImDocument::parse(content).ok()?;
+    let mut item: &Item = doc.as_item();
+    let mut span = None;
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => {
+                let (key, next) = item.as_table_like()?.get_key_value(key)?;
+                span = key.span().or(span);
+                item = next;
+            }
+            PathSegment::Index(i) => return item.as_array()?.get(*i)?.span().or(span),
+        }
+    }
+    item.as_value().and_then(|v| v.span()).or(span)
+}
+
+#[derive(Default)]
+struct YamlEvents(Vec<(YamlEvent, Marker)>);
+
+impl MarkedEventReceiver for YamlEvents {
+    fn on_event(&mut self, event: YamlEvent, mark: Marker) {
+        self.0.push((event, mark));
+    }
+}
+
+fn locate_yaml(content: &str, path: &[PathSegment]) -> Option<Range<usize>> {
+    let mut events = YamlEvents::default();
+    Parser::new_from_str(content)
+        .load(&mut events, false)
+        .ok()?;
+    let events = &events.0;
+    let mut i = events
+        .iter()
+        .position(|(e, _)| matches!(e, YamlEvent::MappingStart(..)))?;
+    find_in_node(content, events, &mut i, path)
+}
+
+/// Walks the node starting at `events[*i]` (leaving `i` after it) and returns
+/// the span of whatever `path` leads to inside it.
+fn find_in_node(
+    content: &str,
+    events: &[(YamlEvent, Marker)],
+    i: &mut usize,
+    path: &[PathSegment],
+) -> Option<Range<usize>> {
+    let (event, mark) = events.get(*i)?;
+    *i += 1;
+    let Some((segment, rest)) = path.split_first() else {
+        skip_rest_of_node(events, i, event);
+        return Some(node_span(content, event, mark));
+    };
+    let mut found = None;
+    match (event, segment) {
+        (YamlEvent::MappingStart(..), _) => {
+            while !matches!(events.get(*i), Some((YamlEvent::MappingEnd, _)) | None) {
+                let (key, key_mark) = &events[*i];
+                let key_span = node_span(content, key, key_mark);
+                let wanted = matches!(
+                    (key, segment),
+                    (YamlEvent::Scalar(k, ..), PathSegment::Key(want)) if k == want
+                );
+                *i += 1;
+                if wanted && found.is_none() {
+                    let value_is_scalar =
+                        matches!(events.get(*i), Some((YamlEvent::Scalar(..), _)));
+                    let inner = find_in_node(content, events, i, rest);
+                    // Like TOML, a table-valued key is reported at the key.
+                    found = match (rest.is_empty(), value_is_scalar) {
+                        (true, false) => Some(key_span),
+                        _ => inner.or(Some(key_span)),
+                    };
+                } else {
+                    skip_node(events, i);
+                }
+            }
+            *i += 1;
+        }
+        (YamlEvent::SequenceStart(..), PathSegment::Index(want)) => {
+            let mut index = 0;
+            while !matches!(events.get(*i), Some((YamlEvent::SequenceEnd, _)) | None) {
+                if index == *want {
+                    found = find_in_node(content, events, i, rest);
+                } else {
+                    skip_node(events, i);
+                }
+                index += 1;
+            }
+            *i += 1;
+        }
+        _ => skip_rest_of_node(events, i, event),
+    }
+    found
+}
+
+fn skip_node(events: &[(YamlEvent, Marker)], i: &mut usize) {
+    if let Some((event, _)) = events.get(*i) {
+        *i += 1;
+        skip_rest_of_node(events, i, event);
+    }
+}
+
+/// Moves `i` past the end of a container whose start event was `start`.
+fn skip_rest_of_node(events: &[(YamlEvent, Marker)], i: &mut usize, start: &YamlEvent) {
+    if !matches!(
+        start,
+        YamlEvent::MappingStart(..) | YamlEvent::SequenceStart(..)
+    ) {
+        return;
+    }
+    let mut depth = 1;
+    while let Some((event, _)) = events.get(*i) {
+        *i += 1;
+        match event {
+            YamlEvent::MappingStart(..) | YamlEvent::SequenceStart(..) => depth += 1,
+            YamlEvent::MappingEnd | YamlEvent::SequenceEnd => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A scalar spans its source text, quotes included; a container just its
+/// first character.
+fn node_span(content: &str, event: &YamlEvent, mark: &Marker) -> Range<usize> {
+    let start = content
+        .char_indices()
+        .nth(mark.index())
+        .map(|(offset, _)| offset)
+        .unwrap_or(content.len());
+    let rest = &content[start..];
+    let len = match (event, rest.chars().next()) {
+        (YamlEvent::Scalar(..), Some(quote @ ('"' | '\''))) => {
+            let mut escaped = false;
+            rest.char_indices()
+                .skip(1)
+                .find(|(_, c)| {
+                    let closes = *c == quote && !escaped;
+                    escaped = quote == '"' && *c == '\\' && !escaped;
+                    closes
+                })
+                .map(|(end, _)| end + 1)
+                .unwrap_or(1)
+        }
+        (YamlEvent::Scalar(value, ..), _) if !value.is_empty() => rest
+            .char_indices()
+            .nth(value.chars().count())
+            .map(|(end, _)| end)
+            .unwrap_or(rest.len()),
+        _ => rest.chars().next().map_or(0, char::len_utf8),
+    };
+    start..start + len
+}
+
+/// Converts a 1-based line and byte column, as serde_json reports them, into
+/// a byte offset on a character boundary.
+pub fn offset_of(content: &str, line: usize, column: usize) -> Option<usize> {
+    let line_start = if line <= 1 {
+        0
+    } else {
+        content.match_indices('\n').nth(line - 2)?.0 + 1
+    };
+    let mut offset = (line_start + column.saturating_sub(1)).min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(segments: &[&str]) -> Vec<PathSegment> {
+        segments
+            .iter()
+            .map(|s| match s.parse() {
+                Ok(i) => PathSegment::Index(i),
+                Err(_) => PathSegment::Key(s.to_string()),
+            })
+            .collect()
+    }
+
+    fn located<'a>(content: &'a str, format: ConfigFormat, segments: &[&str]) -> &'a str {
+        let span = locate(content, format, &path(segments)).expect("span");
+        &content[span]
+    }
+
+    #[test]
+    fn yaml_and_json_spans_point_at_the_value() {
+        let yaml =
+            "services:\n  wéb:\n    command: \"run\"\n    depends_on:\n      - db\n      - cache\n";
+        assert_eq!(
+            located(yaml, ConfigFormat::Yaml, &["services", "wéb", "command"]),
+            "\"run\""
+        );
+        assert_eq!(
+            located(
+                yaml,
+                ConfigFormat::Yaml,
+                &["services", "wéb", "depends_on", "1"]
+            ),
+            "cache"
+        );
+        assert_eq!(
+            located(yaml, ConfigFormat::Yaml, &["services", "wéb"]),
+            "wéb"
+        );
+        assert_eq!(
+            located(yaml, ConfigFormat::Yaml, &["services", "wéb", "missing"]),
+            "wéb"
+        );
+
+        let json =
+            "{\n\t\"services\": {\n\t\t\"wéb\": {\"command\": \"run\", \"delay\": 5}\n\t}\n}\n";
+        assert_eq!(
+            located(json, ConfigFormat::Json, &["services", "wéb", "delay"]),
+            "5"
+        );
+        assert_eq!(
+            located(json, ConfigFormat::Json, &["services", "wéb", "command"]),
+            "\"run\""
+        );
+    }
+
+    #[test]
+    fn offset_of_treats_columns_as_bytes() {
+        let content = "{\n  \"é\": x\n}";
+        let offset = offset_of(content, 2, 9).expect("offset");
+        assert_eq!(&content[offset..offset + 1], "x");
+    }
+}
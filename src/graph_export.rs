@@ -0,0 +1,257 @@
+use crate::stepn_config::StepnConfig;
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Serialize)]
+struct GraphJson<'a> {
+    services: Vec<ServiceJson<'a>>,
+    edges: Vec<EdgeJson<'a>>,
+    cycles: Vec<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct ServiceJson<'a> {
+    name: &'a str,
+    command: &'a str,
+    depends_on: Vec<&'a str>,
+    dependents: Vec<String>,
+    environment_keys: Vec<&'a str>,
+    health: HealthJson<'a>,
+    delay_sec: Option<u64>,
+    restart: RestartJson,
+    in_cycle: bool,
+}
+
+#[derive(Serialize)]
+struct HealthJson<'a> {
+    output_trigger: Vec<&'a str>,
+    /// Probe command, if readiness also waits for one.
+    command: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct RestartJson {
+    enabled: bool,
+    /// `null` when restarts are unlimited.
+    max_restarts: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct EdgeJson<'a> {
+    /// The service that has to be ready first.
+    from: &'a str,
+    /// The service that waits for `from`.
+    to: &'a str,
+}
+
+fn sorted_names(config: &StepnConfig) -> Vec<&str> {
+    let mut names: Vec<&str> = config.services.keys().map(|s| s.as_str()).collect();
+    names.sort();
+    names
+}
+
+/// Dependency edges in start order (dependency -> dependent), sorted. Edges to
+/// undefined services are left out; they still show up in `depends_on`.
+fn edges(config: &StepnConfig) -> Vec<(&str, &str)> {
+    let mut edges: Vec<(&str, &str)> = config
+        .services
+        .iter()
+        .flat_map(|(name, service)| {
+            service
+                .depends_on
+                .iter()
+                .flatten()
+                .filter(|dep| config.services.contains_key(*dep))
+                .map(move |dep| (dep.as_str(), name.as_str()))
+        })
+        .collect();
+    edges.sort();
+    edges.dedup();
+    edges
+}
+
+fn cycle_edges(cycles: &[Vec<String>]) -> HashSet<(&str, &str)> {
+    cycles
+        .iter()
+        .flat_map(|cycle| {
+            // cycle[i] depends on cycle[i + 1], so the edge runs the other way.
+            (0..cycle.len())
+                .map(move |i| (cycle[(i + 1) % cycle.len()].as_str(), cycle[i].as_str()))
+        })
+        .collect()
+}
+
+fn health_summary(config: &StepnConfig, name: &str) -> Vec<String> {
+    let service = &config.services[name];
+    let mut lines = Vec::new();
+    if let Some(triggers) = service
+        .health_checker
+        .as_ref()
+        .and_then(|hc| hc.output_trigger.as_ref())
+        .filter(|t| !t.is_empty())
+    {
+        lines.push(format!("ready on: {}", triggers.join(", ")));
+    }
+    if let Some(command) = service
+        .health_checker
+        .as_ref()
+        .and_then(|hc| hc.command.as_ref())
+    {
+        lines.push(format!("probe: {}", command));
+    }
+    if let Some(delay) = service.delay_sec {
+        lines.push(format!("delay: {}s", delay));
+    }
+    if service.restart {
+        match service.effective_max_restarts() {
+            u32::MAX => lines.push("restart: infinite".to_string()),
+            max => lines.push(format!("restart: max {}", max)),
+        }
+    }
+    lines
+}
+
+pub fn to_json(config: &StepnConfig) -> String {
+    let cycles = config.cycles();
+    let in_cycle: HashSet<&str> = cycles.iter().flatten().map(|s| s.as_str()).collect();
+
+    let services = sorted_names(config)
+        .into_iter()
+        .map(|name| {
+            let service = &config.services[name];
+            let mut environment_keys: Vec<&str> = service
+                .environments
+                .iter()
+                .flatten()
+                .map(|(k, _)| k.as_str())
+                .collect();
+            environment_keys.sort();
+            let max = service.effective_max_restarts();
+            ServiceJson {
+                name,
+                command: &service.command,
+                depends_on: service
+                    .depends_on
+                    .iter()
+                    .flatten()
+                    .map(|s| s.as_str())
+                    .collect(),
+                dependents: config.dependents_of(name),
+                environment_keys,
+                health: HealthJson {
+                    output_trigger: service
+                        .health_checker
+                        .iter()
+                        .flat_map(|hc| hc.output_trigger.iter().flatten())
+                        .map(|s| s.as_str())
+                        .collect(),
+                    command: service
+                        .health_checker
+                        .as_ref()
+                        .and_then(|hc| hc.command.as_deref()),
+                },
+                delay_sec: service.delay_sec,
+                restart: RestartJson {
+                    enabled: service.restart,
+                    max_restarts: (max != u32::MAX).then_some(max),
+                },
+                in_cycle: in_cycle.contains(name),
+            }
+        })
+        .collect();
+
+    let edges = edges(config)
+        .into_iter()
+        .map(|(from, to)| EdgeJson { from, to })
+        .collect();
+
+    serde_json::to_string_pretty(&GraphJson {
+        services,
+        edges,
+        cycles: cycles.clone(),
+    })
+    .expect("graph is always serializable")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn to_dot(config: &StepnConfig) -> String {
+    let cycles = config.cycles();
+    let in_cycle = cycle_edges(&cycles);
+
+    let mut out = String::from("digraph stepn {\n    rankdir=LR;\n    node [shape=box];\n");
+    for name in sorted_names(config) {
+        let mut label = vec![name.to_string(), config.services[name].command.clone()];
+        label.extend(health_summary(config, name));
+        let label = label
+            .iter()
+            .map(|l| dot_escape(l))
+            .collect::<Vec<_>>()
+            .join("\\n");
+        out.push_str(&format!(
+            "    \"{}\" [label=\"{}\"];\n",
+            dot_escape(name),
+            label
+        ));
+    }
+    for (from, to) in edges(config) {
+        let attrs = if in_cycle.contains(&(from, to)) {
+            " [color=red]"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "    \"{}\" -> \"{}\"{};\n",
+            dot_escape(from),
+            dot_escape(to),
+            attrs
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn mermaid_escape(s: &str) -> String {
+    s.replace('&', "#amp;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+pub fn to_mermaid(config: &StepnConfig) -> String {
+    let cycles = config.cycles();
+    let in_cycle = cycle_edges(&cycles);
+    let names = sorted_names(config);
+    // Service names may contain characters Mermaid does not accept in ids.
+    let id = |name: &str| format!("s{}", names.iter().position(|n| *n == name).unwrap_or(0));
+
+    let mut out = String::from("flowchart LR\n");
+    for name in &names {
+        let mut label = vec![
+            format!("<b>{}</b>", mermaid_escape(name)),
+            mermaid_escape(&config.services[*name].command),
+        ];
+        label.extend(
+            health_summary(config, name)
+                .iter()
+                .map(|l| mermaid_escape(l)),
+        );
+        out.push_str(&format!("    {}[\"{}\"]\n", id(name), label.join("<br/>")));
+    }
+    let mut cycle_links = Vec::new();
+    for (i, (from, to)) in edges(config).into_iter().enumerate() {
+        out.push_str(&format!("    {} --> {}\n", id(from), id(to)));
+        if in_cycle.contains(&(from, to)) {
+            cycle_links.push(i.to_string());
+        }
+    }
+    if !cycle_links.is_empty() {
+        out.push_str(&format!(
+            "    linkStyle {} stroke:red\n",
+            cycle_links.join(",")
+        ));
+    }
+    out
+}
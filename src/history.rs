@@ -0,0 +1,109 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+pub const DEFAULT_HISTORY_LINES: usize = 200;
+
+/// Lines a `stepn logs -f` client may fall behind by before it skips some.
+const LIVE_BUFFER_LINES: usize = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedLine {
+    pub time: DateTime<Local>,
+    pub is_stderr: bool,
+    pub text: String,
+    pub pid: Option<u32>,
+    pub restart: u32,
+}
+
+/// A line together with the name of the service that wrote it.
+pub type ServiceLine = (String, RecordedLine);
+
+impl RecordedLine {
+    pub fn now(is_stderr: bool, text: &str, pid: Option<u32>, restart: u32) -> Self {
+        RecordedLine {
+            time: Local::now(),
+            is_stderr,
+            text: text.to_string(),
+            pid,
+            restart,
+        }
+    }
+}
+
+/// The most recent output lines of every service, kept regardless of
+/// filters so they can be shown after a crash or by `stepn logs`.
+pub struct History {
+    capacity: usize,
+    services: Mutex<HashMap<String, VecDeque<RecordedLine>>>,
+    live: broadcast::Sender<ServiceLine>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            services: Mutex::new(HashMap::new()),
+            live: broadcast::channel(LIVE_BUFFER_LINES).0,
+        }
+    }
+
+    pub fn push(&self, service: &str, line: RecordedLine) {
+        let mut services = self.services.lock().expect("lock poisoned");
+        // Fails only when nobody is following, which is fine.
+        let _ = self.live.send((service.to_string(), line.clone()));
+        if self.capacity == 0 {
+            return;
+        }
+        let lines = services.entry(service.to_string()).or_default();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The last `n` lines of each of `services` recorded at or after `since`,
+    /// oldest first, together with a receiver for every line pushed after them.
+    pub fn follow(
+        &self,
+        services: &[String],
+        n: usize,
+        since: Option<DateTime<Local>>,
+    ) -> (Vec<ServiceLine>, broadcast::Receiver<ServiceLine>) {
+        let recorded = self.services.lock().expect("lock poisoned");
+        let mut lines: Vec<ServiceLine> = services
+            .iter()
+            .flat_map(|service| {
+                let kept: Vec<&RecordedLine> = recorded
+                    .get(service)
+                    .into_iter()
+                    .flatten()
+                    .filter(|line| since.is_none_or(|since| line.time >= since))
+                    .collect();
+                let skip = kept.len().saturating_sub(n);
+                kept.into_iter()
+                    .skip(skip)
+                    .map(|line| (service.clone(), line.clone()))
+            })
+            .collect();
+        lines.sort_by_key(|(_, line)| line.time);
+        (lines, self.live.subscribe())
+    }
+
+    /// Up to `n` of the service's most recent lines, oldest first.
+    pub fn tail(&self, service: &str, n: usize) -> Vec<RecordedLine> {
+        let services = self.services.lock().expect("lock poisoned");
+        services
+            .get(service)
+            .map(|lines| {
+                lines
+                    .iter()
+                    .skip(lines.len().saturating_sub(n))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,133 @@
+use futures::Stream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longer lines are split into pieces of at most this many bytes.
+pub const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// An unterminated line (e.g. a `Password:` prompt or a progress bar) is
+/// passed on once the process has been quiet for this long.
+pub const IDLE_FLUSH: Duration = Duration::from_millis(500);
+
+/// Splits a child's output into lines. Unlike `LinesCodec` it never fails:
+/// invalid UTF-8 is replaced, over-long lines are split, and read errors end
+/// the stream like EOF.
+pub struct LineReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub fn new(inner: R) -> Self {
+        LineReader {
+            inner,
+            buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    pub async fn next_line(&mut self) -> Option<String> {
+        let mut chunk = [0u8; 8192];
+        loop {
+            let window = &self.buf[..self.buf.len().min(MAX_LINE_BYTES + 1)];
+            if let Some(pos) = window.iter().position(|b| *b == b'\n') {
+                let mut line: Vec<u8> = self.buf.drain(..=pos).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Some(decode(line));
+            }
+            if self.buf.len() > MAX_LINE_BYTES {
+                // Don't cut a multi-byte character in half.
+                let mut at = MAX_LINE_BYTES;
+                while at > 0 && (self.buf[at] & 0xC0) == 0x80 {
+                    at -= 1;
+                }
+                let rest = self
+                    .buf
+                    .split_off(if at == 0 { MAX_LINE_BYTES } else { at });
+                return Some(decode(std::mem::replace(&mut self.buf, rest)));
+            }
+            if self.eof {
+                return (!self.buf.is_empty()).then(|| decode(std::mem::take(&mut self.buf)));
+            }
+
+            let read = if self.buf.is_empty() {
+                self.inner.read(&mut chunk).await
+            } else {
+                match tokio::time::timeout(IDLE_FLUSH, self.inner.read(&mut chunk)).await {
+                    Ok(read) => read,
+                    Err(_) => return Some(decode(std::mem::take(&mut self.buf))),
+                }
+            };
+            match read {
+                Ok(0) | Err(_) => self.eof = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = String> {
+        futures::stream::unfold(self, |mut reader| async move {
+            reader.next_line().await.map(|line| (line, reader))
+        })
+    }
+}
+
+fn decode(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    async fn lines(input: &[u8]) -> Vec<String> {
+        let mut reader = LineReader::new(input);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn splits_on_newlines_and_keeps_the_unterminated_rest() {
+        assert_eq!(
+            lines(b"one\ntwo\r\n\nthree").await,
+            vec!["one", "two", "", "three"]
+        );
+        assert!(lines(b"").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_utf8() {
+        assert_eq!(lines(b"a\xffb\n").await, vec!["a\u{fffd}b"]);
+    }
+
+    #[tokio::test]
+    async fn splits_long_lines_without_cutting_characters() {
+        let mut input = vec![b'a'; MAX_LINE_BYTES - 1];
+        input.extend_from_slice("é".as_bytes());
+        input.extend_from_slice(b"tail\n");
+        let lines = lines(&input).await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), MAX_LINE_BYTES - 1);
+        assert_eq!(lines[1], "étail");
+    }
+
+    #[tokio::test]
+    async fn flushes_a_partial_line_once_the_process_goes_quiet() {
+        let (mut writer, read_half) = tokio::io::duplex(64);
+        let mut reader = LineReader::new(read_half);
+        writer.write_all(b"Password: ").await.unwrap();
+        let line = tokio::time::timeout(IDLE_FLUSH * 4, reader.next_line()).await;
+        assert_eq!(line.unwrap().as_deref(), Some("Password: "));
+        // Output after the flush starts a new line.
+        writer.write_all(b"done\n").await.unwrap();
+        assert_eq!(reader.next_line().await.as_deref(), Some("done"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::stepn_config::{depends_on_path, service_field_path, ConfigIssue, StepnConfig};
+
+/// Checks for configs that are valid but probably not what the author meant.
+pub fn lint(config: &StepnConfig) -> Vec<ConfigIssue> {
+    let mut names: Vec<&String> = config.services.keys().collect();
+    names.sort();
+    let mut warnings = Vec::new();
+
+    for name in names {
+        let service = &config.services[name];
+        let deps = service.depends_on.as_deref().unwrap_or_default();
+
+        if service.max_restarts.is_some() && !service.restart {
+            warnings.push(ConfigIssue {
+                message: format!(
+                    "service '{}' sets max_restarts but restart is not enabled",
+                    name
+                ),
+                path: service_field_path(name, "max_restarts"),
+                help: Some("add `restart = true` or remove max_restarts".to_string()),
+            });
+        }
+
+        if let Some(hc) = &service.health_checker {
+            if !hc.has_signal() {
+                warnings.push(ConfigIssue {
+                    message: format!(
+                        "service '{}' has a health_checker with no output_trigger or command",
+                        name
+                    ),
+                    path: service_field_path(name, "health_checker"),
+                    help: Some(
+                        "the service is marked ready on its first output line; add an output_trigger or a probe command"
+                            .to_string(),
+                    ),
+                });
+            }
+        }
+
+        for (i, dep) in deps.iter().enumerate() {
+            let Some(dep_service) = config.services.get(dep) else {
+                continue;
+            };
+            let has_signal = dep_service
+                .health_checker
+                .as_ref()
+                .is_some_and(|hc| hc.has_signal());
+            if !has_signal {
+                warnings.push(ConfigIssue {
+                    message: format!(
+                        "service '{}' depends on '{}', which has no readiness signal",
+                        name, dep
+                    ),
+                    path: depends_on_path(name, i),
+                    help: Some(format!(
+                        "'{}' is marked ready on its first output line; add a health_checker.output_trigger or command to it",
+                        dep
+                    )),
+                });
+            }
+        }
+
+        if let Some(syntax) = bash_only_syntax(&service.command) {
+            warnings.push(ConfigIssue {
+                message: format!(
+                    "command of service '{}' uses bash-only syntax ({}), but runs under `sh -c`",
+                    name, syntax
+                ),
+                path: service_field_path(name, "command"),
+                help: Some("wrap it as `bash -c '...'` or use POSIX sh syntax".to_string()),
+            });
+        }
+
+        if service.delay_sec.is_some() && deps.is_empty() {
+            warnings.push(ConfigIssue {
+                message: format!(
+                    "service '{}' has delay_sec but no dependencies to wait for",
+                    name
+                ),
+                path: service_field_path(name, "delay_sec"),
+                help: Some("delay_sec counts from when dependencies are ready".to_string()),
+            });
+        }
+    }
+
+    warnings
+}
+
+/// Returns a short description of the first bash-only construct found in `command`.
+fn bash_only_syntax(command: &str) -> Option<&'static str> {
+    if command.contains("$'") {
+        return Some("ANSI-C quoting `$'...'`");
+    }
+    let unquoted = strip_quotes(command);
+    if has_brace_range(&unquoted) {
+        return Some("brace expansion `{a..b}`");
+    }
+    let words: Vec<&str> = unquoted
+        .split(|c: char| c.is_whitespace() || ";&|()".contains(c))
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.contains(&"[[") {
+        return Some("`[[ ... ]]` test");
+    }
+    if words.windows(2).any(|pair| pair[0] == "function") {
+        return Some("`function` keyword");
+    }
+    [
+        ("<(", "process substitution `<(...)`"),
+        (">(", "process substitution `>(...)`"),
+        ("&>", "`&>` redirection"),
+    ]
+    .into_iter()
+    .find(|(needle, _)| unquoted.contains(needle))
+    .map(|(_, description)| description)
+}
+
+/// Replaces quoted and escaped text with `_`, so only shell syntax is left to
+/// match against.
+fn strip_quotes(command: &str) -> String {
+    let mut result = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+                result.push('_');
+            }
+            '\'' | '"' => {
+                let mut escaped = false;
+                for inner in chars.by_ref() {
+                    if c == '"' && !escaped && inner == '\\' {
+                        escaped = true;
+                        continue;
+                    }
+                    if inner == c && !escaped {
+                        break;
+                    }
+                    escaped = false;
+                }
+                result.push('_');
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Matches `{0..10}` / `{a..z}`: a brace group containing `..` and no whitespace.
+fn has_brace_range(command: &str) -> bool {
+    command.match_indices('{').any(|(start, _)| {
+        let rest = &command[start + 1..];
+        rest.find('}').is_some_and(|end| {
+            let inner = &rest[..end];
+            inner.contains("..")
+                && !inner.starts_with("..")
+                && !inner.ends_with("..")
+                && !inner.contains(char::is_whitespace)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bash_only_syntax_is_found_in_shell_code() {
+        assert!(bash_only_syntax("[[ -f x ]] && echo y").is_some());
+        assert!(bash_only_syntax("true; function f { :; }").is_some());
+        assert!(bash_only_syntax("diff <(ls a) <(ls b)").is_some());
+        assert!(bash_only_syntax("echo {1..3}").is_some());
+    }
+
+    #[test]
+    fn bash_only_syntax_ignores_quoted_text_and_partial_words() {
+        assert_eq!(bash_only_syntax("grep '[[:digit:]]' file"), None);
+        assert_eq!(bash_only_syntax("echo \"function called\""), None);
+        assert_eq!(bash_only_syntax("python -m my_function x"), None);
+        assert_eq!(bash_only_syntax("echo '{1..3}'"), None);
+        assert_eq!(bash_only_syntax("echo a\\[[b"), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::stepn_config::{LogRotation, RotationInterval};
+use crate::writer::{Overflow, BUFFER_LINES};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_KEEP: usize = 5;
+/// Put in front of lines the service wrote to stderr.
+const STDERR_MARKER: &str = "[stderr] ";
+/// Put in front of stepn's own notes in the file.
+const STEPN_MARKER: &str = "[stepn] ";
+
+/// Appends a service's raw output to a file, rotating it to `<file>.1`,
+/// `<file>.2`, ... by size or time and keeping a bounded number of old files.
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: u64,
+    max_bytes: Option<u64>,
+    interval: Option<RotationInterval>,
+    keep: usize,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, rotation: Option<&LogRotation>) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let interval = rotation.and_then(|r| r.interval);
+        // An existing file keeps the period it was last written in, so a
+        // restart on the next day still rotates yesterday's log away.
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            size: metadata.len(),
+            period: period_of(modified, interval),
+            max_bytes: rotation
+                .and_then(|r| r.max_size_mb)
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+            interval,
+            keep: rotation.and_then(|r| r.keep).unwrap_or(DEFAULT_KEEP),
+        })
+    }
+
+    /// Moves the file to a thread of its own, so writes and rotation never
+    /// block the async runtime. Up to `BUFFER_LINES` lines wait for the
+    /// thread; past that `policy` applies. `on_error` is called once if a
+    /// write fails; later lines are discarded.
+    pub fn spawn(
+        mut self,
+        policy: Overflow,
+        on_error: impl FnOnce(io::Error) + Send + 'static,
+    ) -> LogWriter {
+        let (entries, received) = sync_channel::<Entry>(BUFFER_LINES);
+        std::thread::spawn(move || {
+            for entry in received {
+                let result = match entry {
+                    Entry::Line(is_stderr, line) => self.write_line(is_stderr, &line),
+                    Entry::Dropped(lines) => self.write_note(&format!(
+                        "{} lines dropped, the log file fell behind",
+                        lines
+                    )),
+                };
+                if let Err(e) = result {
+                    on_error(e);
+                    return;
+                }
+            }
+        });
+        LogWriter {
+            entries,
+            policy,
+            dropped: 0,
+        }
+    }
+
+    pub fn write_line(&mut self, is_stderr: bool, line: &str) -> io::Result<()> {
+        let marker = if is_stderr { STDERR_MARKER } else { "" };
+        self.write_marked(marker, line)
+    }
+
+    fn write_note(&mut self, note: &str) -> io::Result<()> {
+        self.write_marked(STEPN_MARKER, note)
+    }
+
+    fn write_marked(&mut self, marker: &str, line: &str) -> io::Result<()> {
+        let len = (marker.len() + line.len()) as u64 + 1;
+        let now = period_of(SystemTime::now(), self.interval);
+        let too_big = self
+            .max_bytes
+            .is_some_and(|max| self.size > 0 && self.size + len > max);
+        if too_big || now != self.period {
+            self.rotate()?;
+            self.period = now;
+        }
+        self.file.write_all(marker.as_bytes())?;
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(numbered(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    std::fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+enum Entry {
+    Line(bool, String),
+    Dropped(u64),
+}
+
+/// Hands lines to a `LogFile` running on its own thread.
+pub struct LogWriter {
+    entries: SyncSender<Entry>,
+    policy: Overflow,
+    /// Lines discarded since the last note in the file.
+    dropped: u64,
+}
+
+impl LogWriter {
+    pub fn write_line(&mut self, is_stderr: bool, line: &str) {
+        let entry = Entry::Line(is_stderr, line.to_string());
+        if self.policy == Overflow::Block {
+            // Sending fails only after the file reported an error and stopped.
+            let _ = tokio::task::block_in_place(|| self.entries.send(entry));
+            return;
+        }
+        if self.dropped > 0 {
+            match self.entries.try_send(Entry::Dropped(self.dropped)) {
+                Ok(()) => self.dropped = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    return;
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+        }
+        // Lines already handed to the file thread can't be taken back, so
+        // under `drop-oldest` the new line is dropped too, as with `summarise`.
+        if let Err(TrySendError::Full(_)) = self.entries.try_send(entry) {
+            self.dropped += 1;
+        }
+    }
+}
+
+fn period_of(time: SystemTime, interval: Option<RotationInterval>) -> u64 {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    match interval {
+        None => 0,
+        Some(RotationInterval::Hourly) => secs / 3600,
+        Some(RotationInterval::Daily) => secs / 86400,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stepn-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_a_bounded_number_of_files() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("web.log");
+        let rotation = LogRotation {
+            max_size_mb: Some(0),
+            interval: None,
+            keep: Some(2),
+        };
+        // With a limit of 0 bytes every line after the first rotates the file.
+        let mut log = LogFile::open(&path, Some(&rotation)).unwrap();
+        for line in ["one", "two", "three", "four"] {
+            log.write_line(false, line).unwrap();
+        }
+        assert_eq!(read(&path), "four\n");
+        assert_eq!(read(&dir.join("web.log.1")), "three\n");
+        assert_eq!(read(&dir.join("web.log.2")), "two\n");
+        assert!(!dir.join("web.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn huge_size_limits_do_not_overflow() {
+        let dir = temp_dir("huge");
+        let path = dir.join("web.log");
+        let rotation = LogRotation {
+            max_size_mb: Some(u64::MAX),
+            interval: None,
+            keep: None,
+        };
+        let mut log = LogFile::open(&path, Some(&rotation)).unwrap();
+        log.write_line(false, "one").unwrap();
+        log.write_line(false, "two").unwrap();
+        assert_eq!(read(&path), "one\ntwo\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn marks_stderr_lines_and_appends_to_existing_files() {
+        let dir = temp_dir("append");
+        let path = dir.join("web.log");
+        LogFile::open(&path, None)
+            .unwrap()
+            .write_line(false, "out")
+            .unwrap();
+        LogFile::open(&path, None)
+            .unwrap()
+            .write_line(true, "err")
+            .unwrap();
+        assert_eq!(read(&path), "out\n[stderr] err\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,21 +1,51 @@
-use crate::stepn_config::{read_config, StepnConfig};
-use crate::util::{compute_label_width, pad_with_trailing_space};
+use crate::color::{service_color, ColorChoice};
+use crate::control::{Client, Connection, ControlPaths, Request, Response};
+use crate::diagnostic::Severity;
+use crate::history::{History, DEFAULT_HISTORY_LINES};
+use crate::line_reader::LineReader;
+use crate::output::{Filter, LogFormat, Output, ServiceOutput};
+use crate::status::{ServiceState, ServiceStatus, StatusBoard};
+use crate::stepn_config::{
+    issue_diagnostics, parse_config, read_config, read_config_unvalidated, ConfigFormat,
+    StepnConfig, DEFAULT_PROBE_INTERVAL_SEC,
+};
+use crate::supervisor::{service_env, Supervisor, SHELL, STOP_TIMEOUT};
+use crate::util::{compute_label_width, pad_with_trailing_space, parse_duration};
+use crate::writer::Overflow;
 use colored::Colorize;
-use futures::future::join_all;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::IsTerminal;
+use std::os::unix::process::CommandExt;
 use std::process::Stdio;
-use std::sync::{Arc, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::thread;
 use std::time::Duration;
 use sysinfo::{Pid, System};
-use tokio_stream::StreamExt;
 
+mod color;
+mod compose;
+mod control;
+mod diagnostic;
+mod graph_export;
+mod history;
+mod line_reader;
+mod lint;
+mod log_file;
+mod output;
+mod plan;
+mod prefix;
+mod procfile;
+mod status;
 mod stepn_config;
+mod supervisor;
+mod tui;
 mod util;
+mod writer;
 
-use seahorse::Context;
+use seahorse::{Context, Flag, FlagType};
 use tokio::process::Command;
-use tokio_util::codec::{FramedRead, LinesCodec};
+use tokio::sync::broadcast;
 
 static CONFIG_PATH: OnceLock<String> = OnceLock::new();
 
@@ -25,7 +55,7 @@ static CONFIG: std::sync::LazyLock<StepnConfig> = std::sync::LazyLock::new(|| {
         .cloned()
         .unwrap_or_else(|| "proc.toml".to_string());
     read_config(&path).unwrap_or_else(|e| {
-        eprintln!("error: {}", e);
+        eprint!("{}", e);
         std::process::exit(1);
     })
 });
@@ -39,7 +69,14 @@ fn extract_config_flag(args: &mut Vec<String>) -> Option<String> {
         let val = args.remove(i);
         return Some(val.trim_start_matches("--file=").to_string());
     }
-    if let Some(i) = args.iter().position(|a| a == "-f" || a == "--file") {
+    // After the `logs` subcommand, `-f` means --follow.
+    let subcommand = subcommand_index(args);
+    let logs = args.get(subcommand).is_some_and(|a| a == "logs");
+    if let Some(i) = args
+        .iter()
+        .enumerate()
+        .position(|(i, a)| (a == "-f" && !(logs && i > subcommand)) || a == "--file")
+    {
         args.remove(i);
         if i < args.len() {
             return Some(args.remove(i));
@@ -51,6 +88,39 @@ fn extract_config_flag(args: &mut Vec<String>) -> Option<String> {
     None
 }
 
+/// Index of the subcommand in `args`, past the global flags and their values.
+fn subcommand_index(args: &[String]) -> usize {
+    let mut i = 1;
+    while let Some(arg) = args.get(i) {
+        match arg.as_str() {
+            "-f" | "--file" | "--color" => i += 2,
+            a if a.starts_with("--file=") || a.starts_with("--color=") => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+/// Only looks before the subcommand, so `--color` can still be passed to the
+/// commands stepn runs (`stepn execute web grep --color=always x`).
+fn extract_color_flag(args: &mut Vec<String>) -> Option<String> {
+    let global = &args[..subcommand_index(args).min(args.len())];
+    if let Some(i) = global.iter().position(|a| a.starts_with("--color=")) {
+        let val = args.remove(i);
+        return Some(val.trim_start_matches("--color=").to_string());
+    }
+    if let Some(i) = global.iter().position(|a| a == "--color") {
+        args.remove(i);
+        if i < args.len() {
+            return Some(args.remove(i));
+        } else {
+            eprintln!("error: --color requires auto, always or never");
+            std::process::exit(1);
+        }
+    }
+    None
+}
+
 fn main() {
     let mut args: Vec<String> = std::env::args().collect();
     if args.iter().any(|a| a == "-v" || a == "--version") {
@@ -60,23 +130,104 @@ fn main() {
     if let Some(path) = extract_config_flag(&mut args) {
         CONFIG_PATH.set(path).expect("CONFIG_PATH already set");
     }
+    let color = extract_color_flag(&mut args).unwrap_or_else(|| "auto".to_string());
+    ColorChoice::parse(&color)
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        })
+        .apply();
     let app = seahorse::App::new(env!("CARGO_PKG_NAME"))
         .description(env!("CARGO_PKG_DESCRIPTION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .version(env!("CARGO_PKG_VERSION"))
-        .usage("stepn [-f <path>] [command] [args]")
+        .usage("stepn [-f <path>] [--color=auto|always|never] [command] [args]")
         .action(|c| {
             build_runtime().block_on(run(c));
         })
         .command(
-            seahorse::Command::new("run")
-                .description("run services from config (optionally specify service names)")
-                .alias("r")
-                .usage("stepn run(r) [service1 service2 ...]")
+            run_flags().into_iter().fold(
+                seahorse::Command::new("run")
+                    .description("run services from config (optionally specify service names)")
+                    .alias("r")
+                    .usage("stepn run(r) [--dry-run] [service1 service2 ...]")
+                    .action(|c| {
+                        build_runtime().block_on(run(c));
+                    }),
+                |cmd, flag| cmd.flag(flag),
+            ),
+        )
+        .command(
+            run_flags().into_iter().fold(
+                seahorse::Command::new("up")
+                    .description("run services, with -d in the background")
+                    .usage("stepn up [-d] [service1 service2 ...]")
+                    .flag(
+                        Flag::new("detach", FlagType::Bool)
+                            .alias("d")
+                            .description("run the supervisor in the background"),
+                    )
+                    .action(up),
+                |cmd, flag| cmd.flag(flag),
+            ),
+        )
+        .command(
+            seahorse::Command::new("status")
+                .description("show the services of the stack running here")
+                .usage("stepn status")
+                .action(|_| build_runtime().block_on(status())),
+        )
+        .command(
+            seahorse::Command::new("start")
+                .description("start stopped services of the stack running here")
+                .usage("stepn start service1 [service2 ...]")
+                .action(|c| build_runtime().block_on(control_services(c, "start"))),
+        )
+        .command(
+            seahorse::Command::new("stop")
+                .description("stop the stack running here, or only the given services")
+                .usage("stepn stop [--cascade] [service1 service2 ...]")
+                .flag(cascade_flag())
                 .action(|c| {
-                    build_runtime().block_on(run(c));
+                    if c.args.is_empty() {
+                        build_runtime().block_on(stop_stack(false))
+                    } else {
+                        build_runtime().block_on(control_services(c, "stop"))
+                    }
                 }),
         )
+        .command(
+            seahorse::Command::new("restart")
+                .description("restart services of the stack running here")
+                .usage("stepn restart [--cascade] service1 [service2 ...]")
+                .flag(cascade_flag())
+                .action(|c| build_runtime().block_on(control_services(c, "restart"))),
+        )
+        .command(
+            seahorse::Command::new("down")
+                .description("stop the stack running here and remove its .stepn directory")
+                .usage("stepn down")
+                .action(|_| build_runtime().block_on(stop_stack(true))),
+        )
+        .command(
+            seahorse::Command::new("logs")
+                .description("show output of the stack running here")
+                .usage("stepn logs [-f] [--tail N] [--since 5m] [service1 service2 ...]")
+                .flag(
+                    Flag::new("follow", FlagType::Bool)
+                        .alias("f")
+                        .description("keep printing new output until interrupted"),
+                )
+                .flag(
+                    Flag::new("tail", FlagType::Int)
+                        .description("only the last N lines of each service"),
+                )
+                .flag(
+                    Flag::new("since", FlagType::String)
+                        .description("only lines from the last 30s, 5m, 2h, 1d..."),
+                )
+                .action(|c| build_runtime().block_on(logs(c))),
+        )
         .command(
             seahorse::Command::new("execute")
                 .description("execute oneshot command in a service's environment")
@@ -90,24 +241,105 @@ fn main() {
             seahorse::Command::new("validate")
                 .description("validate config file")
                 .alias("v")
-                .usage("stepn validate(v)")
-                .action(|_c| {
-                    validate();
-                }),
+                .usage("stepn validate(v) [--lint] [--deny-warnings]")
+                .flag(
+                    Flag::new("lint", FlagType::Bool)
+                        .description("also warn about configs that are valid but probably wrong"),
+                )
+                .flag(
+                    Flag::new("deny-warnings", FlagType::Bool)
+                        .description("exit with an error if --lint reports any warning"),
+                )
+                .action(validate),
         )
         .command(
             seahorse::Command::new("list")
                 .description("list services and dependency tree")
                 .alias("l")
-                .usage("stepn list(l)")
-                .action(|_c| {
-                    list();
-                }),
+                .usage("stepn list(l) [--format text|json|dot|mermaid]")
+                .flag(
+                    Flag::new("format", FlagType::String)
+                        .description("output format: text (default), json, dot or mermaid"),
+                )
+                .action(list),
+        )
+        .command(
+            seahorse::Command::new("plan")
+                .description("show startup waves and the critical path without running anything")
+                .alias("p")
+                .usage("stepn plan(p) [service1 service2 ...]")
+                .action(plan),
+        )
+        .command(
+            seahorse::Command::new("import")
+                .description("convert another process definition format into proc.toml")
+                .usage("stepn import <format> [path]")
+                .command(
+                    seahorse::Command::new("procfile")
+                        .description("convert a Foreman/Overmind-style Procfile")
+                        .usage("stepn import procfile [Procfile] [--output <path>] [--force]")
+                        .flag(
+                            Flag::new("output", FlagType::String)
+                                .alias("o")
+                                .description(
+                                    "write to <path> instead of proc.toml (`-` for stdout)",
+                                ),
+                        )
+                        .flag(
+                            Flag::new("force", FlagType::Bool)
+                                .description("overwrite the output file if it exists"),
+                        )
+                        .action(import_procfile),
+                )
+                .command(
+                    seahorse::Command::new("compose")
+                        .description("convert a docker-compose file")
+                        .usage("stepn import compose [docker-compose.yml] [--raw] [--output <path>] [--force]")
+                        .flag(
+                            Flag::new("raw", FlagType::Bool).description(
+                                "run each service's own command on the host instead of `docker compose run`",
+                            ),
+                        )
+                        .flag(
+                            Flag::new("output", FlagType::String)
+                                .alias("o")
+                                .description("write to <path> instead of proc.toml (`-` for stdout)"),
+                        )
+                        .flag(
+                            Flag::new("force", FlagType::Bool)
+                                .description("overwrite the output file if it exists"),
+                        )
+                        .action(import_compose),
+                ),
         );
 
+    let app = run_flags()
+        .into_iter()
+        .fold(app, |app, flag| app.flag(flag));
     app.run(args);
 }
 
+/// Flags shared by `stepn run` and the bare `stepn` invocation.
+fn run_flags() -> Vec<Flag> {
+    vec![
+        Flag::new("dry-run", FlagType::Bool)
+            .description("print what would be spawned, in start order, without launching anything"),
+        Flag::new("tui", FlagType::Bool)
+            .description("full-screen dashboard with a service list and log pane"),
+        Flag::new("log-format", FlagType::String)
+            .description("output format: text (default) or json, one JSON object per line"),
+        Flag::new("only", FlagType::String)
+            .description("show output only from these services (comma separated)"),
+        Flag::new("exclude", FlagType::String)
+            .description("hide output from these services (comma separated)"),
+        Flag::new("grep", FlagType::String).description("show only lines matching this regex"),
+        Flag::new("quiet-until-ready", FlagType::Bool)
+            .description("hide a service's output once it has become ready"),
+        Flag::new("on-overflow", FlagType::String)
+            .description("when output can't keep up: summarise (default), drop-oldest or block"),
+    ]
+}
+
 fn config_path() -> String {
     CONFIG_PATH
         .get()
@@ -115,26 +347,155 @@ fn config_path() -> String {
         .unwrap_or_else(|| "proc.toml".to_string())
 }
 
-fn validate() {
+fn validate(c: &Context) {
     let path = config_path();
     match read_config(&path) {
         Ok(config) => {
+            let warnings = if c.bool_flag("lint") {
+                lint::lint(&config)
+            } else {
+                Vec::new()
+            };
+            let warning_count = warnings.len();
+            if warning_count > 0 {
+                let content = std::fs::read_to_string(&path).unwrap_or_default();
+                eprintln!(
+                    "{}",
+                    issue_diagnostics(&path, content, warnings, Severity::Warning)
+                );
+                if c.bool_flag("deny-warnings") {
+                    eprintln!(
+                        "{}: {} has {} {} (denied by --deny-warnings)",
+                        "error".red().bold(),
+                        path,
+                        warning_count,
+                        if warning_count == 1 {
+                            "warning"
+                        } else {
+                            "warnings"
+                        }
+                    );
+                    std::process::exit(1);
+                }
+            }
             println!(
-                "{} {} is valid ({} services)",
+                "{} {} is valid ({} services{})",
                 "OK:".green(),
                 path,
-                config.services.len()
+                config.services.len(),
+                match warning_count {
+                    0 => String::new(),
+                    1 => ", 1 warning".to_string(),
+                    n => format!(", {} warnings", n),
+                }
             );
         }
         Err(e) => {
-            eprintln!("{} {}\n{:#}", "ERROR:".red(), path, e);
+            eprintln!("{}", e);
+            eprintln!(
+                "{}: {} is invalid ({} {})",
+                "error".red().bold(),
+                path,
+                e.items.len(),
+                if e.items.len() == 1 {
+                    "error"
+                } else {
+                    "errors"
+                }
+            );
             std::process::exit(1);
         }
     }
 }
 
-fn list() {
-    let config = &*CONFIG;
+fn import_procfile(c: &Context) {
+    let source = c.args.first().map(|s| s.as_str()).unwrap_or("Procfile");
+    let config = std::fs::read_to_string(source)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(parse_config(&content, ConfigFormat::Procfile)?))
+        .unwrap_or_else(|e| {
+            eprintln!("error: failed to import {}: {}", source, e);
+            std::process::exit(1);
+        });
+    write_imported_config(c, source, &config);
+}
+
+fn import_compose(c: &Context) {
+    let source = c.args.first().cloned().unwrap_or_else(|| {
+        ["compose.yaml", "compose.yml", "docker-compose.yaml"]
+            .into_iter()
+            .find(|p| std::path::Path::new(p).exists())
+            .unwrap_or("docker-compose.yml")
+            .to_string()
+    });
+    let imported = compose::import_compose(&source, c.bool_flag("raw")).unwrap_or_else(|e| {
+        eprintln!("error: failed to import {}: {:#}", source, e);
+        std::process::exit(1);
+    });
+    if let Err(e) = imported.config.validate() {
+        eprintln!("error: failed to import {}: {}", source, e);
+        std::process::exit(1);
+    }
+    for warning in &imported.warnings {
+        eprintln!("{} {}", "WARNING:".yellow(), warning);
+    }
+    write_imported_config(c, &source, &imported.config);
+}
+
+fn write_imported_config(c: &Context, source: &str, config: &StepnConfig) {
+    let output = c
+        .string_flag("output")
+        .unwrap_or_else(|_| "proc.toml".to_string());
+    let toml = config.to_toml().unwrap_or_else(|e| {
+        eprintln!("error: failed to serialize config: {}", e);
+        std::process::exit(1);
+    });
+
+    if output == "-" {
+        print!("{}", toml);
+        return;
+    }
+    if std::path::Path::new(&output).exists() && !c.bool_flag("force") {
+        eprintln!(
+            "error: {} already exists (use --force to overwrite, or --output <path>)",
+            output
+        );
+        std::process::exit(1);
+    }
+    std::fs::write(&output, toml).unwrap_or_else(|e| {
+        eprintln!("error: failed to write {}: {}", output, e);
+        std::process::exit(1);
+    });
+    println!(
+        "{} imported {} services from {} into {}",
+        "OK:".green(),
+        config.services.len(),
+        source,
+        output
+    );
+}
+
+fn list(c: &Context) {
+    // Load without validation so a broken dependency graph can still be inspected.
+    let config = &read_config_unvalidated(&config_path()).unwrap_or_else(|e| {
+        eprint!("{}", e);
+        std::process::exit(1);
+    });
+    match c.string_flag("format").as_deref() {
+        Err(_) | Ok("text") => {}
+        Ok("json") => return println!("{}", graph_export::to_json(config)),
+        Ok("dot") => return print!("{}", graph_export::to_dot(config)),
+        Ok("mermaid") => return print!("{}", graph_export::to_mermaid(config)),
+        Ok(other) => {
+            eprintln!(
+                "error: unknown format '{}' (expected text, json, dot or mermaid)",
+                other
+            );
+            std::process::exit(1);
+        }
+    }
+    let cycles = config.cycles();
+    let in_cycle: HashSet<&str> = cycles.iter().flatten().map(|s| s.as_str()).collect();
 
     let mut names: Vec<&String> = config.services.keys().collect();
     names.sort();
@@ -142,7 +503,11 @@ fn list() {
     println!("{}", "Services:".green().bold());
     for name in &names {
         let service = &config.services[*name];
-        println!("  {} {}", "*".green(), name.bold());
+        if in_cycle.contains(name.as_str()) {
+            println!("  {} {} {}", "!".red(), name.bold(), "(circular)".red());
+        } else {
+            println!("  {} {}", "*".green(), name.bold());
+        }
         println!("    command: {}", service.command);
         if let Some(deps) = &service.depends_on {
             if !deps.is_empty() {
@@ -153,6 +518,9 @@ fn list() {
             if let Some(triggers) = &hc.output_trigger {
                 println!("    health_checker: [{}]", triggers.join(", "));
             }
+            if let Some(command) = &hc.command {
+                println!("    health_checker command: {}", command);
+            }
         }
         if let Some(delay) = service.delay_sec {
             println!("    delay_sec: {}", delay);
@@ -180,11 +548,37 @@ fn list() {
         .collect();
 
     for (i, root) in roots.iter().enumerate() {
-        print_tree(config, root, "", i == roots.len() - 1, true);
+        print_tree(
+            config,
+            root,
+            "",
+            i == roots.len() - 1,
+            true,
+            &mut Vec::new(),
+        );
+    }
+
+    if !cycles.is_empty() {
+        println!("\n{}", "Circular Dependencies:".red().bold());
+        for cycle in &cycles {
+            println!(
+                "  {} {} -> {}",
+                "!".red(),
+                cycle.join(" -> ").red(),
+                cycle[0].red()
+            );
+        }
     }
 }
 
-fn print_tree(config: &StepnConfig, name: &str, prefix: &str, is_last: bool, is_root: bool) {
+fn print_tree(
+    config: &StepnConfig,
+    name: &str,
+    prefix: &str,
+    is_last: bool,
+    is_root: bool,
+    ancestors: &mut Vec<String>,
+) {
     let connector = if is_root {
         ""
     } else if is_last {
@@ -200,6 +594,17 @@ fn print_tree(config: &StepnConfig, name: &str, prefix: &str, is_last: bool, is_
         .map(|deps| format!(" (depends on: {})", deps.join(", ")))
         .unwrap_or_default();
 
+    if ancestors.iter().any(|a| a == name) {
+        println!(
+            "{}{}{}{} {}",
+            prefix,
+            connector,
+            name.bold(),
+            dep_info,
+            "(circular)".red()
+        );
+        return;
+    }
     println!("{}{}{}{}", prefix, connector, name.bold(), dep_info);
 
     let child_prefix = if is_root {
@@ -210,10 +615,19 @@ fn print_tree(config: &StepnConfig, name: &str, prefix: &str, is_last: bool, is_
         format!("{}│   ", prefix)
     };
 
+    ancestors.push(name.to_string());
     let children = config.dependents_of(name);
     for (i, child) in children.iter().enumerate() {
-        print_tree(config, child, &child_prefix, i == children.len() - 1, false);
+        print_tree(
+            config,
+            child,
+            &child_prefix,
+            i == children.len() - 1,
+            false,
+            ancestors,
+        );
     }
+    ancestors.pop();
 }
 
 async fn execute(con: &Context) {
@@ -232,9 +646,12 @@ async fn execute(con: &Context) {
             eprintln!("error: service '{}' is not defined", service_name);
             std::process::exit(1);
         });
-    let oneshot_command = con
-        .args
-        .get(1..con.args.len())
+    // Taken from the raw arguments, since seahorse splits `--flag=value` into
+    // two arguments even after the subcommand.
+    let raw_args: Vec<String> = std::env::args().collect();
+    let oneshot_command = raw_args
+        .get(subcommand_index(&raw_args) + 2..)
+        .filter(|command| !command.is_empty())
         .unwrap_or_else(|| {
             eprintln!("error: command not passed");
             std::process::exit(1);
@@ -243,17 +660,12 @@ async fn execute(con: &Context) {
 
     let label_width = compute_label_width(std::iter::once(&service_name));
 
-    let mut cmd = Command::new("sh");
+    let mut cmd = Command::new(SHELL);
     cmd.arg("-c")
         .arg(oneshot_command.join(" "))
-        .env("IS_STEPN", "true")
+        .envs(service_env(service))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
-    if let Some(env) = &service.environments {
-        for (k, v) in env {
-            cmd.env(k, v);
-        }
-    }
 
     let mut child = cmd.spawn().unwrap_or_else(|e| {
         eprintln!(
@@ -265,223 +677,1023 @@ async fn execute(con: &Context) {
     });
 
     let stdout = child.stdout.take().expect("stdout not captured");
-    let mut reader = FramedRead::new(stdout, LinesCodec::new());
-    while let Some(Ok(line)) = reader.next().await {
-        println!(
-            "{}{} {}",
-            pad_with_trailing_space(label_width, &service_name).blue(),
-            ": ".green(),
-            line
-        );
+    let stderr = child.stderr.take().expect("stderr not captured");
+    let prefix = format!("{}: ", pad_with_trailing_space(label_width, &service_name))
+        .color(service_color(&CONFIG, &service_name));
+    // Both pipes are read, so a command that writes a lot to one of them
+    // never blocks on the other.
+    let print_stdout = async {
+        let mut reader = LineReader::new(stdout);
+        while let Some(line) = reader.next_line().await {
+            println!("{} {}", prefix, line);
+        }
+    };
+    let print_stderr = async {
+        let mut reader = LineReader::new(stderr);
+        while let Some(line) = reader.next_line().await {
+            eprintln!("{} {}", prefix, line);
+        }
+    };
+    tokio::join!(print_stdout, print_stderr);
+    let _ = child.wait().await;
+}
+
+/// Splits a comma separated `--only`/`--exclude` value into service names.
+fn service_list_flag(c: &Context, name: &str) -> Option<Vec<String>> {
+    let names: Vec<String> = c
+        .string_flag(name)
+        .ok()?
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    ensure_services_defined(&names);
+    Some(names)
+}
+
+fn output_filter(c: &Context) -> Filter {
+    let grep = c.string_flag("grep").ok().map(|pattern| {
+        regex::Regex::new(&pattern).unwrap_or_else(|e| {
+            eprintln!("error: invalid --grep pattern: {}", e);
+            std::process::exit(1);
+        })
+    });
+    Filter {
+        only: service_list_flag(c, "only"),
+        exclude: service_list_flag(c, "exclude").unwrap_or_default(),
+        grep,
+        quiet_after_ready: c.bool_flag("quiet-until-ready"),
     }
 }
 
-async fn run(c: &Context) {
-    let service_names: Vec<String> = if c.args.is_empty() {
+/// Exits with the list of available services if any of `names` is not defined.
+fn ensure_services_defined(names: &[String]) {
+    for name in names {
+        if !CONFIG.services.contains_key(name) {
+            eprintln!("error: service '{}' is not defined", name);
+            let mut available: Vec<&String> = CONFIG.services.keys().collect();
+            available.sort();
+            eprintln!(
+                "available services: {}",
+                available
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn plan(c: &Context) {
+    let selected: HashSet<String> = if c.args.is_empty() {
         CONFIG.services.keys().cloned().collect()
     } else {
-        for name in &c.args {
-            if !CONFIG.services.contains_key(name) {
-                eprintln!("error: service '{}' is not defined", name);
-                let mut available: Vec<&String> = CONFIG.services.keys().collect();
-                available.sort();
-                eprintln!(
-                    "available services: {}",
-                    available
+        ensure_services_defined(&c.args);
+        CONFIG.resolve_transitive_deps(&c.args)
+    };
+    let plan = plan::StartupPlan::new(&CONFIG, &selected);
+    let critical: HashSet<&str> = plan.critical_path.iter().map(|s| s.as_str()).collect();
+    let label_width = compute_label_width(selected.iter());
+
+    println!(
+        "{} {} services in {} waves",
+        "Startup Plan:".green().bold(),
+        selected.len(),
+        plan.waves.len()
+    );
+    for (i, wave) in plan.waves.iter().enumerate() {
+        println!("\n{}", format!("Wave {}", i + 1).bold());
+        for name in wave {
+            let service = &CONFIG.services[name];
+            let mut details = Vec::new();
+            let deps: Vec<&str> = service
+                .depends_on
+                .iter()
+                .flatten()
+                .map(|s| s.as_str())
+                .collect();
+            if !deps.is_empty() {
+                details.push(format!("after {}", deps.join(", ")));
+            }
+            if let Some(delay) = service.delay_sec {
+                details.push(format!(
+                    "delay {}s, {}s stacked",
+                    delay, plan.stacked_delay[name]
+                ));
+            }
+            let marker = if critical.contains(name.as_str()) {
+                "*".yellow()
+            } else {
+                " ".normal()
+            };
+            if details.is_empty() {
+                println!("  {} {}", marker, name.bold());
+            } else {
+                println!(
+                    "  {} {}  ({})",
+                    marker,
+                    pad_with_trailing_space(label_width, name).bold(),
+                    details.join("; ")
+                );
+            }
+        }
+    }
+
+    if let Some(last) = plan.critical_path.last() {
+        println!(
+            "\n{} {} services, {}s of delay_sec",
+            "Critical Path:".yellow().bold(),
+            plan.critical_path.len(),
+            plan.stacked_delay[last]
+        );
+        println!("  {}", plan.critical_path.join(" -> ").yellow());
+        println!(
+            "  {}",
+            "Time to become ready is not included: timings from recorded runs are not used."
+                .dimmed()
+        );
+    }
+}
+
+fn dry_run(service_names: &[String]) {
+    let selected: HashSet<String> = service_names.iter().cloned().collect();
+    let plan = plan::StartupPlan::new(&CONFIG, &selected);
+    let cwd = std::env::current_dir()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "(unknown)".to_string());
+
+    println!(
+        "{} {} services, nothing will be started",
+        "Dry run:".green().bold(),
+        selected.len()
+    );
+    let mut order = 0;
+    for (wave, names) in plan.waves.iter().enumerate() {
+        for name in names {
+            order += 1;
+            let service = &CONFIG.services[name];
+            println!("\n[{}] {} (wave {})", order, name.green().bold(), wave + 1);
+            if let Some(deps) = service.depends_on.as_ref().filter(|d| !d.is_empty()) {
+                println!("    waits for: {}", deps.join(", "));
+            }
+            if let Some(delay) = service.delay_sec {
+                println!("    delay:     {}s after dependencies are ready", delay);
+            }
+            println!("    shell:     {} -c", SHELL);
+            println!("    command:   {}", service.command);
+            println!("    cwd:       {}", cwd);
+            for (i, (k, v)) in service_env(service).iter().enumerate() {
+                let overrides = if std::env::var(k).is_ok_and(|current| current != *v) {
+                    " (overrides inherited value)".dimmed().to_string()
+                } else {
+                    String::new()
+                };
+                let label = if i == 0 { "env:      " } else { "          " };
+                println!("    {} {}={}{}", label, k, v, overrides);
+            }
+            let health_checker = service.health_checker.as_ref();
+            let mut ready = Vec::new();
+            if let Some(triggers) = health_checker
+                .and_then(|hc| hc.output_trigger.as_ref())
+                .filter(|t| !t.is_empty())
+            {
+                ready.push(format!(
+                    "output contains {}",
+                    triggers
                         .iter()
-                        .map(|s| s.as_str())
+                        .map(|t| format!("{:?}", t))
                         .collect::<Vec<_>>()
-                        .join(", ")
-                );
-                std::process::exit(1);
+                        .join(" and ")
+                ));
+            }
+            if let Some(hc) = health_checker {
+                if let Some(command) = &hc.command {
+                    ready.push(format!(
+                        "`{}` exits 0 (every {}s)",
+                        command,
+                        hc.interval_sec.unwrap_or(DEFAULT_PROBE_INTERVAL_SEC)
+                    ));
+                }
+            }
+            if ready.is_empty() {
+                println!("    ready:     on first output line");
+            } else {
+                println!("    ready:     {}", ready.join(", and "));
             }
+            let restart = match service.effective_max_restarts() {
+                _ if !service.restart => "no".to_string(),
+                u32::MAX => "on exit, unlimited".to_string(),
+                max => format!("on exit, up to {} times", max),
+            };
+            println!("    restart:   {}", restart);
         }
-        let resolved = CONFIG.resolve_transitive_deps(&c.args);
-        let mut resolved_sorted: Vec<&str> = resolved.iter().map(|s| s.as_str()).collect();
-        resolved_sorted.sort();
-        println!("Running services: {}", resolved_sorted.join(", "));
-        resolved.into_iter().collect()
+    }
+}
+
+async fn run(c: &Context) {
+    let format = match c.string_flag("log-format") {
+        Ok(s) => LogFormat::parse(&s).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        Err(_) => LogFormat::Text,
     };
+    let overflow = match c.string_flag("on-overflow") {
+        Ok(s) => Overflow::parse(&s).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        Err(_) => Overflow::Summarise,
+    };
+    let output = Output::new(format, output_filter(c), overflow);
+
+    let service_names: Vec<String> = if c.args.is_empty() {
+        CONFIG.services.keys().cloned().collect()
+    } else {
+        ensure_services_defined(&c.args);
+        CONFIG
+            .resolve_transitive_deps(&c.args)
+            .into_iter()
+            .collect()
+    };
+
+    if c.bool_flag("dry-run") {
+        dry_run(&service_names);
+        return;
+    }
+
+    if !c.args.is_empty() {
+        let mut sorted: Vec<&str> = service_names.iter().map(|s| s.as_str()).collect();
+        sorted.sort();
+        output.notice(
+            "selected",
+            &format!("Running services: {}", sorted.join(", ")),
+        );
+    }
+
+    let tui = c.bool_flag("tui");
+    if tui && (format == LogFormat::Json || !std::io::stdout().is_terminal()) {
+        eprintln!("error: --tui needs text output on a terminal");
+        std::process::exit(1);
+    }
 
     let label_width = compute_label_width(service_names.iter());
 
-    let healthcheck_map: HashMap<String, bool> =
-        service_names.iter().map(|k| (k.clone(), false)).collect();
+    let board = StatusBoard::new(
+        service_names
+            .iter()
+            .map(|name| (name, CONFIG.services[name].effective_max_restarts())),
+    );
+    let history = Arc::new(History::new(
+        CONFIG.history_lines.unwrap_or(DEFAULT_HISTORY_LINES),
+    ));
+    let supervisor = Arc::new(Supervisor::spawn(
+        &CONFIG,
+        &service_names,
+        output.clone(),
+        history,
+        board.clone(),
+        label_width,
+    ));
+    let children = supervisor.children();
 
-    let children: Arc<RwLock<Vec<u32>>> = Arc::new(RwLock::new(Vec::new()));
-    let ptr = Arc::clone(&children);
-    ctrlc::set_handler(move || {
-        println!("\nReceived Ctrl+C!");
-        let pids: Vec<u32> = ptr.read().expect("lock poisoned").clone();
-        for pid in &pids {
-            let nix_pid = nix::unistd::Pid::from_raw(*pid as i32);
-            nix::sys::signal::kill(nix_pid, nix::sys::signal::Signal::SIGTERM)
-                .unwrap_or_else(|_| eprintln!("kill signal failed for pid: {}", pid));
+    let paths = ControlPaths::for_config(&config_path());
+    let control_paths = match control::bind(&paths) {
+        Ok(listener) => {
+            tokio::spawn(serve_control(
+                listener,
+                board.clone(),
+                Arc::clone(&supervisor),
+                paths.clone(),
+                output.clone(),
+            ));
+            Some(paths)
         }
-
-        let mut s = System::new_all();
-        for pid in &pids {
-            s.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-            while s.process(Pid::from_u32(*pid)).is_some() {
-                thread::sleep(Duration::from_millis(500));
-                s.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-                eprintln!("waiting for process {} to terminate...", pid);
-            }
+        Err(e) => {
+            eprintln!("warning: {}; runtime control is disabled for this run", e);
+            None
         }
-        std::process::exit(1);
+    };
+
+    let ptr = Arc::clone(&children);
+    let interrupt_output = output.clone();
+    let interrupt_paths = control_paths.clone();
+    ctrlc::set_handler(move || {
+        let message = match interrupt_output.format {
+            LogFormat::Text => "\nReceived Ctrl+C!",
+            LogFormat::Json => "Received Ctrl+C!",
+        };
+        shutdown(
+            &interrupt_output,
+            ("interrupted", message),
+            &ptr,
+            interrupt_paths.as_ref(),
+            false,
+            1,
+        );
     })
     .expect("failed to set Ctrl-C handler");
 
-    let healthcheck_map_ptr: Arc<RwLock<HashMap<String, bool>>> =
-        Arc::new(RwLock::new(healthcheck_map));
+    if !tui && std::io::stdin().is_terminal() {
+        tokio::spawn(stdin_commands(
+            Arc::clone(&supervisor),
+            board.clone(),
+            output.clone(),
+            control_paths.clone(),
+        ));
+    }
 
-    let futures = service_names.iter().map(|name| {
-        let service = &CONFIG.services[name];
-        let name = name.to_string();
-        let healthcheck_map_ptr = Arc::clone(&healthcheck_map_ptr);
-        let children_ptr = Arc::clone(&children);
-        tokio::spawn(async move {
-            if let Some(deps) = &service.depends_on {
-                for dep in deps {
-                    loop {
-                        println!("{} is waiting for {} booting...", name, dep.green());
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        if *healthcheck_map_ptr
-                            .read()
-                            .expect("lock poisoned")
-                            .get(dep.as_str())
-                            .unwrap_or(&false)
-                        {
-                            break;
-                        }
-                    }
+    if tui {
+        output.set_muted(true);
+        let done = Arc::new(AtomicBool::new(false));
+        let supervisor = Arc::clone(&supervisor);
+        let board = board.clone();
+        let thread_done = Arc::clone(&done);
+        let output = output.clone();
+        let paths = control_paths.clone();
+        let handle = thread::spawn(move || {
+            let code = match tui::run(&CONFIG, supervisor, board, thread_done) {
+                Ok(tui::Exit::Finished) => return,
+                Ok(tui::Exit::Quit) => 0,
+                Err(e) => {
+                    eprintln!("error: dashboard failed: {}", e);
+                    1
                 }
-            }
+            };
+            let stopping = ("stopping", "Stopping all services");
+            shutdown(&output, stopping, &children, paths.as_ref(), false, code);
+        });
+        *DASHBOARD.lock().expect("lock poisoned") = Some((done, handle));
+    }
 
-            if let Some(delay_sec) = service.delay_sec {
-                println!("{}: Delaying {} secs", name, delay_sec);
-                tokio::time::sleep(Duration::from_secs(delay_sec)).await;
-            }
+    supervisor.finished().await;
+    // A shutdown already under way (e.g. Ctrl+C racing the last service's
+    // exit) decides the exit code.
+    if SHUTDOWN_STARTED.swap(true, Ordering::SeqCst) {
+        std::future::pending::<()>().await;
+    }
+    let dashboard_output = output.clone();
+    let _ = tokio::task::spawn_blocking(move || close_dashboard(&dashboard_output)).await;
+    if let Some(paths) = &control_paths {
+        control::cleanup(paths);
+    }
+    output.notice("finished", "stepn finished");
+    output.flush_async().await;
+}
 
-            let max_restarts = service.effective_max_restarts();
-            let mut restart_count: u32 = 0;
-
-            loop {
-                let mut pending_triggers: HashMap<String, bool> = service
-                    .health_checker
-                    .as_ref()
-                    .and_then(|hc| hc.output_trigger.as_ref())
-                    .map(|triggers| triggers.iter().map(|t| (t.clone(), false)).collect())
-                    .unwrap_or_default();
-
-                let mut cmd = Command::new("sh");
-                cmd.kill_on_drop(true)
-                    .arg("-c")
-                    .arg(&service.command)
-                    .env("IS_STEPN", "true")
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped());
-                if let Some(env) = &service.environments {
-                    for (k, v) in env {
-                        cmd.env(k, v);
-                    }
-                }
+const STDIN_HELP: &str =
+    "commands: r <service...> restart, l list services, c clear screen, q quit";
 
-                let mut child = cmd.spawn().unwrap_or_else(|e| {
-                    panic!("failed to start command '{}': {}", service.command, e);
+/// Reads commands typed into the terminal while `run` is in the foreground.
+async fn stdin_commands(
+    supervisor: Arc<Supervisor>,
+    board: StatusBoard,
+    output: Output,
+    paths: Option<ControlPaths>,
+) {
+    // A blocking read can't be cancelled, so it runs on a plain thread that
+    // never holds up the runtime when `run` is finished.
+    let (sender, mut lines) = tokio::sync::mpsc::unbounded_channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    while let Some(line) = lines.recv().await {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let services: Vec<String> = words.map(str::to_string).collect();
+        match command {
+            "r" if !services.is_empty() => {
+                let unknown = supervisor.unknown(&services);
+                if !unknown.is_empty() {
+                    let message = format!("not running in this stack: {}", unknown.join(", "));
+                    output.notice("error", &message);
+                    continue;
+                }
+                let restarted = supervisor.restart(&services, false);
+                output.notice("control", &format!("Restarting {}", restarted.join(", ")));
+            }
+            "l" => {
+                let board = board.clone();
+                let services = tokio::task::spawn_blocking(move || board.snapshot_with_usage())
+                    .await
+                    .expect("status task panicked");
+                for row in status_table(&services) {
+                    output.notice("status", &row);
+                }
+            }
+            "c" => output.clear_screen(),
+            "q" => {
+                let children = supervisor.children();
+                let output = output.clone();
+                let paths = paths.clone();
+                tokio::task::spawn_blocking(move || {
+                    let stopping = ("stopping", "Stopping all services");
+                    shutdown(&output, stopping, &children, paths.as_ref(), false, 0);
                 });
+                return;
+            }
+            _ => output.notice("help", STDIN_HELP),
+        }
+    }
+}
+
+/// The `--tui` dashboard's thread, so that shutting down can hand the terminal
+/// back before anything else is printed.
+static DASHBOARD: Mutex<Option<(Arc<AtomicBool>, thread::JoinHandle<()>)>> = Mutex::new(None);
+
+/// Closes the dashboard, if one is open, and waits for it to restore the
+/// terminal.
+fn close_dashboard(output: &Output) {
+    let dashboard = DASHBOARD.lock().expect("lock poisoned").take();
+    if let Some((done, handle)) = dashboard {
+        done.store(true, Ordering::SeqCst);
+        // The dashboard's own thread has already restored the terminal when
+        // it shuts down.
+        if handle.thread().id() != thread::current().id() {
+            let _ = handle.join();
+        }
+    }
+    output.set_muted(false);
+}
+
+/// Set by whichever of `shutdown` or the end of `run` comes first; the other
+/// leaves the exit to it.
+static SHUTDOWN_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Closes the dashboard, prints `notice`, sends SIGTERM to every running
+/// service, waits for them to exit (killing what is left after
+/// `STOP_TIMEOUT`), releases the control socket and exits with `code`.
+fn shutdown(
+    output: &Output,
+    (event, message): (&str, &str),
+    children: &RwLock<Vec<u32>>,
+    paths: Option<&ControlPaths>,
+    remove_state_dir: bool,
+    code: i32,
+) -> ! {
+    if SHUTDOWN_STARTED.swap(true, Ordering::SeqCst) {
+        // The process is already on its way out.
+        loop {
+            thread::park();
+        }
+    }
+    close_dashboard(output);
+    output.notice(event, message);
+    let pids: Vec<u32> = children.read().expect("lock poisoned").clone();
+    for pid in &pids {
+        let nix_pid = nix::unistd::Pid::from_raw(*pid as i32);
+        if nix::sys::signal::killpg(nix_pid, nix::sys::signal::Signal::SIGTERM).is_err() {
+            output.notice("stopping", &format!("kill signal failed for pid: {}", pid));
+        }
+    }
+
+    // Killed services stay zombies until their task reaps them, which may not
+    // happen while we exit, so those count as gone.
+    let alive = |s: &System, pid: u32| {
+        s.process(Pid::from_u32(pid))
+            .is_some_and(|p| p.status() != sysinfo::ProcessStatus::Zombie)
+    };
+    let mut s = System::new_all();
+    let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+    for pid in &pids {
+        s.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+        while alive(&s, *pid) {
+            if std::time::Instant::now() > deadline {
+                let message = format!("process {} did not terminate, killing it", pid);
+                output.notice("stopping", &message);
+                let nix_pid = nix::unistd::Pid::from_raw(*pid as i32);
+                let _ = nix::sys::signal::killpg(nix_pid, nix::sys::signal::Signal::SIGKILL);
+                break;
+            }
+            thread::sleep(Duration::from_millis(500));
+            s.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+            let message = format!("waiting for process {} to terminate...", pid);
+            output.notice("stopping", &message);
+        }
+    }
 
-                let stdout = child.stdout.take().expect("stdout not captured");
-                let child_pid = child.id();
-                if let Some(pid) = child_pid {
-                    children_ptr.write().expect("lock poisoned").push(pid);
+    if let Some(paths) = paths {
+        control::cleanup(paths);
+        if remove_state_dir {
+            let _ = std::fs::remove_dir_all(&paths.dir);
+        }
+    }
+    output.flush();
+    std::process::exit(code);
+}
+
+/// Answers `stepn status`, `start`, `stop`, `restart` and `down` for the
+/// stack this process runs.
+async fn serve_control(
+    listener: tokio::net::UnixListener,
+    board: StatusBoard,
+    supervisor: Arc<Supervisor>,
+    paths: ControlPaths,
+    output: Output,
+) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let board = board.clone();
+        let supervisor = Arc::clone(&supervisor);
+        let paths = paths.clone();
+        let output = output.clone();
+        tokio::spawn(async move {
+            let mut conn = Connection::new(stream);
+            let request = match conn.request().await {
+                Ok(request) => request,
+                Err(e) => {
+                    let message = format!("bad request: {}", e);
+                    let _ = conn.send(&Response::Error { message }).await;
+                    return;
+                }
+            };
+            match request {
+                Request::Status => {
+                    let services = tokio::task::spawn_blocking(move || board.snapshot_with_usage())
+                        .await
+                        .expect("status task panicked");
+                    let _ = conn.send(&Response::Status { services }).await;
                 }
-                let stderr = child.stderr.take().expect("stderr not captured");
-
-                let stdout_reader = FramedRead::new(stdout, LinesCodec::new());
-                let stderr_reader = FramedRead::new(stderr, LinesCodec::new());
-                let mut merged_stream = stdout_reader.merge(stderr_reader.map(|r| {
-                    r.map(|line| format!("{}", format!("*stderr* {}", line).red()))
-                }));
-
-                while let Some(Ok(line)) = merged_stream.next().await {
-                    println!(
-                        "{}{} {}",
-                        pad_with_trailing_space(label_width, &name).green(),
-                        ": ".green(),
-                        line
+                Request::Stop | Request::Down => {
+                    let message = "stopping all services".to_string();
+                    let _ = conn.send(&Response::Ok { message }).await;
+                    let remove_state_dir = matches!(request, Request::Down);
+                    let children = supervisor.children();
+                    tokio::task::spawn_blocking(move || {
+                        let stopping = ("stopping", "Stopping all services (requested by client)");
+                        shutdown(
+                            &output,
+                            stopping,
+                            &children,
+                            Some(&paths),
+                            remove_state_dir,
+                            0,
+                        );
+                    });
+                }
+                Request::StartServices { services }
+                | Request::StopServices { services, .. }
+                | Request::RestartServices { services, .. }
+                | Request::Logs { services, .. }
+                    if !supervisor.unknown(&services).is_empty() =>
+                {
+                    let message = format!(
+                        "not running in this stack: {}",
+                        supervisor.unknown(&services).join(", ")
                     );
-
-                    if pending_triggers.values().any(|done| !done) {
-                        let unmatched: Vec<String> = pending_triggers
-                            .iter()
-                            .filter(|(_, done)| !**done)
-                            .map(|(k, _)| k.clone())
-                            .collect();
-                        for keyword in &unmatched {
-                            if line.contains(keyword.as_str()) {
-                                pending_triggers.insert(keyword.clone(), true);
+                    let _ = conn.send(&Response::Error { message }).await;
+                }
+                Request::Logs {
+                    services,
+                    tail,
+                    since,
+                    follow,
+                } => {
+                    let services = if services.is_empty() {
+                        supervisor.names()
+                    } else {
+                        services
+                    };
+                    let (recent, mut live) =
+                        supervisor
+                            .history()
+                            .follow(&services, tail.unwrap_or(usize::MAX), since);
+                    let header = Response::Logs {
+                        services: services.clone(),
+                        started: output.started,
+                    };
+                    if conn.send(&header).await.is_err() {
+                        return;
+                    }
+                    for (service, line) in recent {
+                        if conn.send(&Response::Line { service, line }).await.is_err() {
+                            return;
+                        }
+                    }
+                    if !follow {
+                        return;
+                    }
+                    loop {
+                        match live.recv().await {
+                            Ok((service, line)) if services.contains(&service) => {
+                                if conn.send(&Response::Line { service, line }).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(broadcast::error::RecvError::Lagged(lines)) => {
+                                if conn.send(&Response::Skipped { lines }).await.is_err() {
+                                    return;
+                                }
                             }
+                            Err(broadcast::error::RecvError::Closed) => return,
                         }
-                    } else if !*healthcheck_map_ptr
-                        .read()
-                        .expect("lock poisoned")
-                        .get(name.as_str())
-                        .unwrap_or(&false)
-                    {
-                        healthcheck_map_ptr
-                            .write()
-                            .expect("lock poisoned")
-                            .insert(name.clone(), true);
                     }
                 }
-
-                if let Some(pid) = child_pid {
-                    children_ptr
-                        .write()
-                        .expect("lock poisoned")
-                        .retain(|p| *p != pid);
+                Request::StartServices { services } => {
+                    let outcome = supervisor.start(&services);
+                    if !outcome.started.is_empty() {
+                        output.notice(
+                            "control",
+                            &format!(
+                                "Starting {} (requested by client)",
+                                outcome.started.join(", ")
+                            ),
+                        );
+                    }
+                    let message = outcome.describe();
+                    let _ = conn.send(&Response::Ok { message }).await;
                 }
-
-                if !service.restart {
-                    break;
+                Request::StopServices { services, cascade } => {
+                    let stopped = supervisor.stop(&services, cascade);
+                    let message = format!("stopping {}", stopped.join(", "));
+                    output.notice(
+                        "control",
+                        &format!("Stopping {} (requested by client)", stopped.join(", ")),
+                    );
+                    let _ = conn.send(&Response::Ok { message }).await;
                 }
-
-                if max_restarts != u32::MAX && restart_count >= max_restarts {
-                    println!(
-                        "{}{} {}",
-                        pad_with_trailing_space(label_width, &name).yellow(),
-                        ": ".yellow(),
-                        "process exited, max restarts reached".red()
+                Request::RestartServices { services, cascade } => {
+                    let restarted = supervisor.restart(&services, cascade);
+                    let message = format!("restarting {}", restarted.join(", "));
+                    output.notice(
+                        "control",
+                        &format!("Restarting {} (requested by client)", restarted.join(", ")),
                     );
-                    break;
+                    let _ = conn.send(&Response::Ok { message }).await;
                 }
+            }
+        });
+    }
+}
 
-                restart_count += 1;
-                let restart_msg = if max_restarts == u32::MAX {
-                    format!("process crashed, restarting... (attempt {})", restart_count)
-                } else {
-                    format!(
-                        "process crashed, restarting... (attempt {}/{})",
-                        restart_count, max_restarts
-                    )
-                };
-                println!(
-                    "{}{} {}",
-                    pad_with_trailing_space(label_width, &name).yellow(),
-                    ": ".yellow(),
-                    restart_msg.yellow()
-                );
-
-                healthcheck_map_ptr
-                    .write()
-                    .expect("lock poisoned")
-                    .insert(name.clone(), false);
+/// `stepn up`: like `run`, or with `-d` re-launched in the background with
+/// its output going to `.stepn/stepn.log`.
+fn up(c: &Context) {
+    if !c.bool_flag("detach") || c.bool_flag("dry-run") {
+        build_runtime().block_on(run(c));
+        return;
+    }
+    if c.bool_flag("tui") {
+        eprintln!("error: --tui cannot be used with -d; use `stepn logs -f` instead");
+        std::process::exit(1);
+    }
+    std::sync::LazyLock::force(&CONFIG);
+    ensure_services_defined(&c.args);
 
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
+    let paths = ControlPaths::for_config(&config_path());
+    if let Some(pid) = control::running_pid(&paths) {
+        eprintln!("error: stepn is already running here (pid {})", pid);
+        std::process::exit(1);
+    }
+    let log = std::fs::create_dir_all(&paths.dir)
+        .and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&paths.log)
         })
+        .unwrap_or_else(|e| {
+            eprintln!("error: cannot open {}: {}", paths.log.display(), e);
+            std::process::exit(1);
+        });
+    let log_err = log
+        .try_clone()
+        .expect("failed to duplicate log file handle");
+
+    // Only the flag given to `up` goes; the same token elsewhere may be a
+    // service name or a global flag's value.
+    let mut args: Vec<String> = std::env::args().collect();
+    let up_args = subcommand_index(&args) + 1;
+    if let Some(i) = args
+        .iter()
+        .skip(up_args)
+        .position(|a| a == "-d" || a == "--detach")
+    {
+        args.remove(up_args + i);
+    }
+    let exe = std::env::current_exe().expect("cannot locate the stepn executable");
+    let mut command = std::process::Command::new(exe);
+    command
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(log_err);
+    // A session of its own keeps Ctrl+C and the terminal's hangup from
+    // reaching it.
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()
+                .map(|_| ())
+                .map_err(std::io::Error::from)
+        });
+    }
+    let mut child = command.spawn().unwrap_or_else(|e| {
+        eprintln!("error: failed to start stepn in the background: {}", e);
+        std::process::exit(1);
     });
-    join_all(futures).await;
-    println!("stepn finished");
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        if control::running_pid(&paths) == Some(child.id()) && paths.socket.exists() {
+            break;
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            eprintln!(
+                "error: stepn exited during startup ({}), see {}",
+                status,
+                paths.log.display()
+            );
+            std::process::exit(1);
+        }
+        if std::time::Instant::now() > deadline {
+            eprintln!(
+                "error: stepn did not open its control socket, see {}",
+                paths.log.display()
+            );
+            std::process::exit(1);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    println!(
+        "{} stepn is running in the background (pid {})",
+        "OK:".green(),
+        child.id()
+    );
+    println!("  logs: {}", paths.log.display());
+    println!("  stop: stepn down");
+}
+
+fn cascade_flag() -> Flag {
+    Flag::new("cascade", FlagType::Bool)
+        .description("also apply to every service that depends on the given ones")
+}
+
+/// `stepn start`, `stop <services>` and `restart`.
+async fn control_services(c: &Context, command: &str) {
+    if c.args.is_empty() {
+        eprintln!(
+            "error: no services given (usage: stepn {} service1 ...)",
+            command
+        );
+        std::process::exit(1);
+    }
+    ensure_services_defined(&c.args);
+    let services = c.args.clone();
+    let cascade = c.bool_flag("cascade");
+    let request = match command {
+        "start" => Request::StartServices { services },
+        "stop" => Request::StopServices { services, cascade },
+        _ => Request::RestartServices { services, cascade },
+    };
+    let paths = ControlPaths::for_config(&config_path());
+    let mut client = connect_or_exit(&paths, &request).await;
+    match client.recv().await {
+        Ok(Some(Response::Ok { message })) => println!("{} {}", "OK:".green(), message),
+        Ok(Some(Response::Error { message })) => {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+        Ok(_) => {
+            eprintln!("error: unexpected reply from stepn");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `stepn logs`: prints recent (and with `-f`, live) output of the stack
+/// running here the way `run` would have.
+async fn logs(c: &Context) {
+    ensure_services_defined(&c.args);
+    let tail = match c.int_flag("tail") {
+        Ok(n) if n >= 0 => Some(n as usize),
+        Ok(_) => {
+            eprintln!("error: --tail must not be negative");
+            std::process::exit(1);
+        }
+        Err(_) => None,
+    };
+    let since = c.string_flag("since").ok().map(|s| {
+        let age = parse_duration(&s).unwrap_or_else(|e| {
+            eprintln!("error: --since: {}", e);
+            std::process::exit(1);
+        });
+        chrono::Duration::from_std(age)
+            .ok()
+            .and_then(|age| chrono::Local::now().checked_sub_signed(age))
+            .unwrap_or_else(|| {
+                eprintln!("error: --since: \"{}\" is too far back", s);
+                std::process::exit(1);
+            })
+    });
+    let request = Request::Logs {
+        services: c.args.clone(),
+        tail,
+        since,
+        follow: c.bool_flag("follow"),
+    };
+    let paths = ControlPaths::for_config(&config_path());
+    let mut client = connect_or_exit(&paths, &request).await;
+    let (services, started) = match client.recv().await {
+        Ok(Some(Response::Logs { services, started })) => (services, started),
+        Ok(Some(Response::Error { message })) => {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+        other => {
+            eprintln!("error: unexpected reply from stepn: {:?}", other);
+            std::process::exit(1);
+        }
+    };
+
+    let mut output = Output::new(LogFormat::Text, Filter::default(), Overflow::Block);
+    output.started = started;
+    let label_width = compute_label_width(services.iter());
+    let mut outs: HashMap<&str, ServiceOutput> = services
+        .iter()
+        .filter(|name| CONFIG.services.contains_key(*name))
+        .map(|name| {
+            let out = ServiceOutput::for_service(output.clone(), &CONFIG, name, label_width);
+            (name.as_str(), out)
+        })
+        .collect();
+    loop {
+        match client.recv().await {
+            Ok(Some(Response::Line { service, line })) => {
+                if let Some(out) = outs.get_mut(service.as_str()) {
+                    out.line(&line).await;
+                }
+            }
+            Ok(Some(Response::Skipped { lines })) => output.notice(
+                "skipped",
+                &format!("{} lines skipped, stepn logs could not keep up", lines),
+            ),
+            Ok(None) => break,
+            other => {
+                output.flush_async().await;
+                eprintln!("error: unexpected reply from stepn: {:?}", other);
+                std::process::exit(1);
+            }
+        }
+    }
+    output.flush_async().await;
+}
+
+async fn connect_or_exit(paths: &ControlPaths, request: &Request) -> Client {
+    Client::connect(paths, request).await.unwrap_or_else(|e| {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    })
+}
+
+async fn status() {
+    let paths = ControlPaths::for_config(&config_path());
+    let mut client = connect_or_exit(&paths, &Request::Status).await;
+    let services = match client.recv().await {
+        Ok(Some(Response::Status { services })) => services,
+        Ok(Some(Response::Error { message })) => {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+        other => {
+            eprintln!("error: unexpected reply from stepn: {:?}", other);
+            std::process::exit(1);
+        }
+    };
+
+    for row in status_table(&services) {
+        println!("{}", row);
+    }
+}
+
+/// The `stepn status` table, header first.
+fn status_table(services: &BTreeMap<String, ServiceStatus>) -> Vec<String> {
+    let label_width = compute_label_width(services.keys()).max("SERVICE".len());
+    let mut rows = vec![format!(
+        "{} {:<9} {:>8} {:>8} {:>8} {:>6} {:>8}  {}",
+        pad_with_trailing_space(label_width, "SERVICE").bold(),
+        "STATE".bold(),
+        "PID".bold(),
+        "UPTIME".bold(),
+        "RESTARTS".bold(),
+        "CPU".bold(),
+        "RSS".bold(),
+        "PENDING".bold()
+    )];
+    let now = chrono::Local::now();
+    for (name, service) in services {
+        let state = format!("{:<9}", service.state.as_str());
+        let state = match service.state {
+            ServiceState::Ready => state.green(),
+            ServiceState::Crashed => state.red(),
+            ServiceState::Exited | ServiceState::Stopped => state.dimmed(),
+            _ => state.yellow(),
+        };
+        let dash = || "-".to_string();
+        let restarts = match service.max_restarts {
+            Some(0) => dash(),
+            Some(max) => format!("{}/{}", service.restarts, max),
+            None => service.restarts.to_string(),
+        };
+        let row = format!(
+            "{} {} {:>8} {:>8} {:>8} {:>6} {:>8}  {}",
+            pad_with_trailing_space(label_width, name),
+            state,
+            service.pid.map(|p| p.to_string()).unwrap_or_else(dash),
+            service
+                .started_at
+                .map(|t| format_uptime(now - t))
+                .unwrap_or_else(dash),
+            restarts,
+            service
+                .cpu_percent
+                .map(|cpu| format!("{:.1}%", cpu))
+                .unwrap_or_else(dash),
+            service.rss_bytes.map(format_bytes).unwrap_or_else(dash),
+            service.pending_triggers.join(", ")
+        );
+        rows.push(row.trim_end().to_string());
+    }
+    rows
+}
+
+fn format_uptime(uptime: chrono::Duration) -> String {
+    let secs = uptime.num_seconds().max(0);
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+/// `stepn stop` and `stepn down`: asks the supervisor to shut down and waits
+/// for it to exit.
+async fn stop_stack(remove_state_dir: bool) {
+    let paths = ControlPaths::for_config(&config_path());
+    let Some(pid) = control::running_pid(&paths) else {
+        if remove_state_dir && paths.dir.exists() {
+            let _ = std::fs::remove_dir_all(&paths.dir);
+            println!(
+                "{} stepn is not running; removed {}",
+                "OK:".green(),
+                paths.dir.display()
+            );
+            return;
+        }
+        eprintln!("error: stepn is not running here");
+        std::process::exit(1);
+    };
+
+    let request = if remove_state_dir {
+        Request::Down
+    } else {
+        Request::Stop
+    };
+    let mut client = connect_or_exit(&paths, &request).await;
+    if let Ok(Some(Response::Error { message })) = client.recv().await {
+        eprintln!("error: {}", message);
+        std::process::exit(1);
+    }
+    // The supervisor kills services that ignore SIGTERM after STOP_TIMEOUT,
+    // so it should be gone well before this.
+    let deadline = tokio::time::Instant::now() + STOP_TIMEOUT * 2;
+    while control::running_pid(&paths).is_some() {
+        if tokio::time::Instant::now() > deadline {
+            eprintln!(
+                "error: stepn (pid {}) did not stop within {}s",
+                pid,
+                (STOP_TIMEOUT * 2).as_secs()
+            );
+            std::process::exit(1);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    println!("{} stepn (pid {}) stopped", "OK:".green(), pid);
 }
@@ -0,0 +1,449 @@
+use crate::color::service_color;
+use crate::history::RecordedLine;
+use crate::prefix::{PrefixContext, PrefixTemplate};
+use crate::stepn_config::{StderrMode, StepnConfig};
+use crate::util::pad_with_trailing_space;
+use crate::writer::{Overflow, Target, Writer};
+use chrono::{DateTime, Local, SecondsFormat};
+use colored::{Color, Colorize};
+use regex::Regex;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// How `run` prints service output and its own lifecycle messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Prefixed, coloured lines for humans.
+    Text,
+    /// One JSON object per line (JSON Lines) for tools.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format \"{}\" (expected text or json)",
+                other
+            )),
+        }
+    }
+}
+
+/// Something stepn itself reports about a service, as opposed to its output.
+pub enum Event<'a> {
+    Waiting {
+        dependency: &'a str,
+    },
+    Delaying {
+        secs: u64,
+    },
+    Started,
+    Ready,
+    Restarting {
+        attempt: u32,
+        max: u32,
+    },
+    Crashed,
+    /// Stopped on request; it stays down until started again.
+    Stopped,
+    Dropped {
+        lines: u64,
+    },
+}
+
+impl Event<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Waiting { .. } => "waiting",
+            Event::Delaying { .. } => "delaying",
+            Event::Started => "started",
+            Event::Ready => "ready",
+            Event::Restarting { .. } => "restarting",
+            Event::Crashed => "crashed",
+            Event::Stopped => "stopped",
+            Event::Dropped { .. } => "dropped",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Event::Waiting { dependency } => format!("waiting for {} booting...", dependency),
+            Event::Delaying { secs } => format!("delaying {} secs", secs),
+            Event::Started => "process started".to_string(),
+            Event::Ready => "service is ready".to_string(),
+            Event::Restarting { attempt, max } if *max == u32::MAX => {
+                format!("process crashed, restarting... (attempt {})", attempt)
+            }
+            Event::Restarting { attempt, max } => {
+                format!(
+                    "process crashed, restarting... (attempt {}/{})",
+                    attempt, max
+                )
+            }
+            Event::Crashed => "process exited, max restarts reached".to_string(),
+            Event::Stopped => "process stopped".to_string(),
+            Event::Dropped { lines } => {
+                format!("{} lines dropped, output could not keep up", lines)
+            }
+        }
+    }
+}
+
+/// Which service output lines reach the console. Readiness matching and log
+/// files always see every line.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Show only these services; `None` shows all of them.
+    pub only: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+    pub grep: Option<Regex>,
+    /// Hide a service's output once it is ready, until it is restarted.
+    pub quiet_after_ready: bool,
+}
+
+impl Filter {
+    fn shows_service(&self, name: &str) -> bool {
+        self.only
+            .as_ref()
+            .is_none_or(|only| only.iter().any(|s| s == name))
+            && !self.exclude.iter().any(|s| s == name)
+    }
+
+    fn shows_line(&self, line: &str) -> bool {
+        self.grep.as_ref().is_none_or(|re| re.is_match(line))
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    service: Option<&'a str>,
+    stream: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a str>,
+    pid: Option<u32>,
+    restart: u32,
+    message: &'a str,
+}
+
+impl JsonLine<'_> {
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("log line is always serializable")
+    }
+}
+
+fn timestamp(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+#[derive(Clone)]
+pub struct Output {
+    pub format: LogFormat,
+    filter: Arc<Filter>,
+    writer: Arc<Writer>,
+    /// Reference point for `{elapsed}` in prefixes.
+    pub started: DateTime<Local>,
+}
+
+impl Output {
+    pub fn new(format: LogFormat, filter: Filter, overflow: Overflow) -> Self {
+        Output {
+            format,
+            filter: Arc::new(filter),
+            writer: Writer::spawn(overflow),
+            started: Local::now(),
+        }
+    }
+
+    /// A message from stepn that is not about any one service.
+    pub fn notice(&self, event: &str, message: &str) {
+        let text = match self.format {
+            LogFormat::Text => message.to_string(),
+            LogFormat::Json => JsonLine {
+                timestamp: timestamp(Local::now()),
+                service: None,
+                stream: "stepn",
+                event: Some(event),
+                pid: None,
+                restart: 0,
+                message,
+            }
+            .to_json(),
+        };
+        self.writer.push(Target::Stdout, text);
+    }
+
+    /// Clears the terminal, in text mode only.
+    pub fn clear_screen(&self) {
+        if self.format == LogFormat::Text {
+            self.writer
+                .push(Target::Stdout, "\x1b[2J\x1b[3J\x1b[H".to_string());
+        }
+    }
+
+    /// The policy for service lines that can't keep up, which log files
+    /// follow as well.
+    pub fn overflow(&self) -> Overflow {
+        self.writer.policy()
+    }
+
+    /// Stops (or resumes) writing to stdout and stderr; muted output is lost.
+    pub fn set_muted(&self, muted: bool) {
+        self.writer.set_muted(muted);
+    }
+
+    /// Waits until everything printed so far has reached the terminal.
+    pub fn flush(&self) {
+        self.writer.flush();
+    }
+
+    /// `flush` for async code, so the wait doesn't tie up a runtime worker.
+    pub async fn flush_async(&self) {
+        let writer = Arc::clone(&self.writer);
+        let _ = tokio::task::spawn_blocking(move || writer.flush()).await;
+    }
+}
+
+/// Prints everything belonging to one service.
+pub struct ServiceOutput<'a> {
+    output: Output,
+    name: &'a str,
+    padded_name: String,
+    prefix: PrefixTemplate,
+    color: Color,
+    time_format: &'a str,
+    /// Print stderr lines on stepn's stderr instead of marking them on stdout.
+    pub separate_stderr: bool,
+    visible: bool,
+    ready: bool,
+    /// Lines discarded under `Overflow::Summarise` and not yet reported.
+    dropped: u64,
+}
+
+impl<'a> ServiceOutput<'a> {
+    pub fn new(
+        output: Output,
+        name: &'a str,
+        padded_name: String,
+        prefix: PrefixTemplate,
+        color: Color,
+        time_format: &'a str,
+    ) -> Self {
+        ServiceOutput {
+            visible: output.filter.shows_service(name),
+            output,
+            name,
+            padded_name,
+            prefix,
+            color,
+            time_format,
+            separate_stderr: false,
+            ready: false,
+            dropped: 0,
+        }
+    }
+
+    /// Output for one service of `config`, with its prefix, colour and
+    /// stderr mode.
+    pub fn for_service(
+        output: Output,
+        config: &'a StepnConfig,
+        name: &'a str,
+        label_width: usize,
+    ) -> Self {
+        let prefix = PrefixTemplate::parse(config.log_prefix(name))
+            .expect("log_prefix is checked by config validation");
+        let mut out = ServiceOutput::new(
+            output,
+            name,
+            pad_with_trailing_space(label_width, name),
+            prefix,
+            service_color(config, name),
+            config.time_format(),
+        );
+        out.separate_stderr = config.stderr_mode(name) == StderrMode::Separate;
+        out
+    }
+
+    fn prefix(&self, stream: &str, pid: Option<u32>, restart: u32) -> String {
+        self.prefix_at(Local::now(), stream, pid, restart)
+    }
+
+    fn prefix_at(
+        &self,
+        time: DateTime<Local>,
+        stream: &str,
+        pid: Option<u32>,
+        restart: u32,
+    ) -> String {
+        let ctx = PrefixContext {
+            name: &self.padded_name,
+            stream,
+            pid,
+            restart,
+            time,
+        };
+        self.prefix
+            .render(&ctx, self.time_format, self.output.started)
+    }
+
+    /// One line the service wrote to stdout or stderr, printed with the time
+    /// it was recorded.
+    pub async fn line(&mut self, recorded: &RecordedLine) {
+        let RecordedLine {
+            time,
+            is_stderr,
+            ref text,
+            pid,
+            restart,
+        } = *recorded;
+        let line = text.as_str();
+        let filter = &self.output.filter;
+        if !self.visible || (self.ready && filter.quiet_after_ready) || !filter.shows_line(line) {
+            return;
+        }
+        let stream = if is_stderr { "stderr" } else { "stdout" };
+        let target = if is_stderr && self.separate_stderr {
+            Target::Stderr
+        } else {
+            Target::Stdout
+        };
+        let text = match self.output.format {
+            LogFormat::Text => {
+                let prefix = self.prefix_at(time, stream, pid, restart).color(self.color);
+                if is_stderr && target == Target::Stdout {
+                    format!("{} {}", prefix, format!("*stderr* {}", line).red())
+                } else {
+                    format!("{} {}", prefix, line)
+                }
+            }
+            LogFormat::Json => JsonLine {
+                timestamp: timestamp(time),
+                service: Some(self.name),
+                stream,
+                event: None,
+                pid,
+                restart,
+                message: line,
+            }
+            .to_json(),
+        };
+
+        let writer = Arc::clone(&self.output.writer);
+        if writer.has_room() {
+            self.report_dropped(pid, restart);
+        }
+        if !writer.push_line(target, text).await && writer.policy() == Overflow::Summarise {
+            self.dropped += 1;
+        }
+    }
+
+    /// Replays the service's last lines after a crash. In JSON each one is
+    /// repeated with a `crash_tail` event, so consumers can pick them out.
+    pub fn crash_tail(&self, lines: &[RecordedLine], pid: Option<u32>, restart: u32) {
+        if lines.is_empty() {
+            return;
+        }
+        let writer = &self.output.writer;
+        if self.output.format == LogFormat::Json {
+            for line in lines {
+                let text = JsonLine {
+                    timestamp: timestamp(line.time),
+                    service: Some(self.name),
+                    stream: if line.is_stderr { "stderr" } else { "stdout" },
+                    event: Some("crash_tail"),
+                    pid: line.pid,
+                    restart: line.restart,
+                    message: &line.text,
+                }
+                .to_json();
+                writer.push(Target::Stdout, text);
+            }
+            return;
+        }
+        let prefix = self.prefix("stepn", pid, restart);
+        writer.push(
+            Target::Stdout,
+            format!(
+                "{} {}",
+                prefix.yellow(),
+                format!("--- last {} lines before the crash ---", lines.len()).yellow()
+            ),
+        );
+        for line in lines {
+            let text = if line.is_stderr {
+                format!("*stderr* {}", line.text).red()
+            } else {
+                line.text.as_str().dimmed()
+            };
+            let time = line.time.format(self.time_format).to_string();
+            writer.push(
+                Target::Stdout,
+                format!(
+                    "{} {} {}",
+                    prefix.yellow(),
+                    format!("{} |", time).yellow(),
+                    text
+                ),
+            );
+        }
+        writer.push(
+            Target::Stdout,
+            format!("{} {}", prefix.yellow(), "--- end ---".yellow()),
+        );
+    }
+
+    /// Reports lines lost under `Overflow::Summarise` since the last report.
+    pub fn report_dropped(&mut self, pid: Option<u32>, restart: u32) {
+        if self.dropped > 0 {
+            let lines = std::mem::take(&mut self.dropped);
+            self.event(Event::Dropped { lines }, pid, restart);
+        }
+    }
+
+    /// Lifecycle events are shown even for filtered-out services, so crashes
+    /// are never hidden.
+    pub fn event(&mut self, event: Event, pid: Option<u32>, restart: u32) {
+        match event {
+            Event::Started => self.ready = false,
+            Event::Ready => self.ready = true,
+            _ => {}
+        }
+        let text = match self.output.format {
+            LogFormat::Text => match event {
+                Event::Waiting { dependency } => format!(
+                    "{} is waiting for {} booting...",
+                    self.name,
+                    dependency.green()
+                ),
+                Event::Delaying { secs } => format!("{}: Delaying {} secs", self.name, secs),
+                // Visible from the service's own output in text mode.
+                Event::Started | Event::Ready => return,
+                Event::Restarting { .. } | Event::Stopped | Event::Dropped { .. } => format!(
+                    "{} {}",
+                    self.prefix("stepn", pid, restart).yellow(),
+                    event.message().yellow()
+                ),
+                Event::Crashed => format!(
+                    "{} {}",
+                    self.prefix("stepn", pid, restart).yellow(),
+                    event.message().red()
+                ),
+            },
+            LogFormat::Json => JsonLine {
+                timestamp: timestamp(Local::now()),
+                service: Some(self.name),
+                stream: "stepn",
+                event: Some(event.name()),
+                pid,
+                restart,
+                message: &event.message(),
+            }
+            .to_json(),
+        };
+        self.output.writer.push(Target::Stdout, text);
+    }
+}
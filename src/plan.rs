@@ -0,0 +1,194 @@
+use crate::stepn_config::StepnConfig;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Startup order derived from `depends_on`, without launching anything.
+pub struct StartupPlan {
+    /// Services that can start in parallel, in start order. Each wave waits for
+    /// the previous ones to become ready.
+    pub waves: Vec<Vec<String>>,
+    /// Sum of `delay_sec` along the slowest dependency chain leading to (and
+    /// including) each service.
+    pub stacked_delay: HashMap<String, u64>,
+    /// The dependency chain with the most stacked `delay_sec`, from a root
+    /// service to its last dependent. Among equally slow chains the longest
+    /// one wins.
+    pub critical_path: Vec<String>,
+}
+
+impl StartupPlan {
+    /// Builds the plan for `selected`, which must be closed over dependencies
+    /// (see `StepnConfig::resolve_transitive_deps`) and acyclic.
+    pub fn new(config: &StepnConfig, selected: &HashSet<String>) -> Self {
+        // A set, so a dependency listed twice is only counted once.
+        let deps_of = |name: &str| -> BTreeSet<&String> {
+            config.services[name]
+                .depends_on
+                .iter()
+                .flatten()
+                .filter(|d| selected.contains(*d))
+                .collect()
+        };
+
+        let mut remaining: HashMap<&str, usize> = selected
+            .iter()
+            .map(|name| (name.as_str(), deps_of(name).len()))
+            .collect();
+        let mut current: Vec<String> = remaining
+            .iter()
+            .filter(|(_, n)| **n == 0)
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let mut waves = Vec::new();
+        while !current.is_empty() {
+            current.sort();
+            let mut next = Vec::new();
+            for name in &current {
+                remaining.remove(name.as_str());
+                for dependent in config.dependents_of(name) {
+                    if let Some(count) = remaining.get_mut(dependent.as_str()) {
+                        *count -= 1;
+                        if *count == 0 {
+                            next.push(dependent);
+                        }
+                    }
+                }
+            }
+            waves.push(std::mem::replace(&mut current, next));
+        }
+
+        // Walk the waves in order so every dependency is settled before its dependents.
+        let mut stacked_delay: HashMap<String, u64> = HashMap::new();
+        let mut depth: HashMap<&str, usize> = HashMap::new();
+        let mut via: HashMap<&str, &str> = HashMap::new();
+        for name in waves.iter().flatten() {
+            let deps = deps_of(name);
+            let base_delay = deps
+                .iter()
+                .map(|dep| stacked_delay[dep.as_str()])
+                .max()
+                .unwrap_or(0);
+            let delay = config.services[name].delay_sec.unwrap_or(0);
+            stacked_delay.insert(name.clone(), base_delay + delay);
+
+            let slowest = deps.into_iter().max_by_key(|dep| {
+                (
+                    stacked_delay[dep.as_str()],
+                    depth[dep.as_str()],
+                    std::cmp::Reverse(dep.as_str()),
+                )
+            });
+            let base_depth = match slowest {
+                Some(dep) => {
+                    via.insert(name, dep);
+                    depth[dep.as_str()]
+                }
+                None => 0,
+            };
+            depth.insert(name, base_depth + 1);
+        }
+
+        let mut critical_path = Vec::new();
+        let mut cursor = waves
+            .iter()
+            .flatten()
+            .max_by_key(|name| {
+                (
+                    stacked_delay[name.as_str()],
+                    depth[name.as_str()],
+                    std::cmp::Reverse(name.as_str()),
+                )
+            })
+            .map(|s| s.as_str());
+        while let Some(name) = cursor {
+            critical_path.push(name.to_string());
+            cursor = via.get(name).copied();
+        }
+        critical_path.reverse();
+
+        StartupPlan {
+            waves,
+            stacked_delay,
+            critical_path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stepn_config::{parse_config, ConfigFormat};
+
+    fn plan(toml: &str) -> StartupPlan {
+        let config = parse_config(toml, ConfigFormat::Toml).expect("valid config");
+        let selected = config.services.keys().cloned().collect();
+        StartupPlan::new(&config, &selected)
+    }
+
+    #[test]
+    fn waves_follow_dependencies() {
+        let plan = plan(
+            r#"
+            [services.db]
+            command = "true"
+            [services.cache]
+            command = "true"
+            [services.web]
+            command = "true"
+            depends_on = ["db", "cache"]
+            [services.worker]
+            command = "true"
+            depends_on = ["web"]
+            "#,
+        );
+        assert_eq!(
+            plan.waves,
+            vec![
+                vec!["cache".to_string(), "db".to_string()],
+                vec!["web".to_string()],
+                vec!["worker".to_string()],
+            ]
+        );
+        assert_eq!(plan.critical_path, vec!["cache", "web", "worker"]);
+    }
+
+    #[test]
+    fn critical_path_follows_the_slowest_chain_not_the_deepest() {
+        let plan = plan(
+            r#"
+            [services.a]
+            command = "true"
+            delay_sec = 100
+            [services.b]
+            command = "true"
+            [services.c]
+            command = "true"
+            depends_on = ["b"]
+            [services.d]
+            command = "true"
+            depends_on = ["a", "c"]
+            delay_sec = 5
+            "#,
+        );
+        assert_eq!(plan.stacked_delay["d"], 105);
+        assert_eq!(plan.stacked_delay["c"], 0);
+        assert_eq!(plan.critical_path, vec!["a", "d"]);
+    }
+
+    #[test]
+    fn duplicate_dependencies_are_counted_once() {
+        let plan = plan(
+            r#"
+            [services.db]
+            command = "true"
+            [services.web]
+            command = "true"
+            depends_on = ["db", "db"]
+            "#,
+        );
+        assert_eq!(
+            plan.waves,
+            vec![vec!["db".to_string()], vec!["web".to_string()]]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+
+pub const DEFAULT_PREFIX: &str = "{name}: ";
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S%.3f";
+
+const PLACEHOLDERS: &[&str] = &["time", "elapsed", "name", "stream", "pid", "restart"];
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+/// A parsed `log_prefix` such as `"[{time}] {name} | "`.
+#[derive(Clone, Debug)]
+pub struct PrefixTemplate {
+    parts: Vec<Part>,
+}
+
+/// Values substituted into a prefix for one line of output.
+pub struct PrefixContext<'a> {
+    /// Service name, already padded to the label width so columns line up.
+    pub name: &'a str,
+    pub stream: &'a str,
+    pub pid: Option<u32>,
+    pub restart: u32,
+    /// When the line was written.
+    pub time: DateTime<Local>,
+}
+
+impl PrefixTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed `{{` in log_prefix \"{}\"", template));
+            };
+            let key = &rest[start + 1..start + len];
+            let Some(placeholder) = PLACEHOLDERS.iter().find(|p| **p == key) else {
+                return Err(format!(
+                    "unknown placeholder `{{{}}}` in log_prefix (expected one of {})",
+                    key,
+                    PLACEHOLDERS
+                        .iter()
+                        .map(|p| format!("{{{}}}", p))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            };
+            parts.push(Part::Placeholder(placeholder));
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(PrefixTemplate { parts })
+    }
+
+    pub fn render(
+        &self,
+        ctx: &PrefixContext,
+        time_format: &str,
+        started: DateTime<Local>,
+    ) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Placeholder("time") => {
+                    out.push_str(&ctx.time.format(time_format).to_string())
+                }
+                Part::Placeholder("elapsed") => {
+                    let elapsed = (ctx.time - started).num_milliseconds().max(0);
+                    out.push_str(&format!("{:.3}s", elapsed as f64 / 1000.0))
+                }
+                Part::Placeholder("name") => out.push_str(ctx.name),
+                Part::Placeholder("stream") => out.push_str(ctx.stream),
+                Part::Placeholder("pid") => match ctx.pid {
+                    Some(pid) => out.push_str(&pid.to_string()),
+                    None => out.push('-'),
+                },
+                Part::Placeholder("restart") => out.push_str(&ctx.restart.to_string()),
+                Part::Placeholder(_) => {}
+            }
+        }
+        out
+    }
+}
+
+/// Checks a strftime-style `time_format` without formatting anything.
+pub fn check_time_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid time_format \"{}\"", format));
+    }
+    Ok(())
+}
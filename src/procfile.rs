@@ -0,0 +1,57 @@
+use crate::stepn_config::{Service, StepnConfig};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// Parses a Foreman/Overmind-style Procfile (`name: command` per line) into a
+/// config where every process is an independent service.
+pub fn parse_procfile(content: &str) -> Result<StepnConfig> {
+    let mut services = HashMap::new();
+
+    for (i, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((name, command)) = line.split_once(':') else {
+            bail!(
+                "line {}: expected `<name>: <command>`, got '{}'",
+                i + 1,
+                line
+            );
+        };
+        let name = name.trim();
+        let command = command.trim();
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("line {}: invalid process name '{}'", i + 1, name);
+        }
+        if command.is_empty() {
+            bail!("line {}: process '{}' has no command", i + 1, name);
+        }
+        if services.contains_key(name) {
+            bail!("line {}: process '{}' is defined twice", i + 1, name);
+        }
+
+        services.insert(
+            name.to_string(),
+            Service {
+                command: command.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+
+    if services.is_empty() {
+        bail!("no processes defined");
+    }
+
+    Ok(StepnConfig {
+        services,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState {
+    /// Waiting for a dependency to become ready.
+    Waiting,
+    /// Sleeping for `delay_sec`.
+    Delaying,
+    /// Running, readiness triggers not matched yet.
+    Starting,
+    Ready,
+    /// Exited and waiting to be restarted, or out of restarts.
+    Crashed,
+    /// Exited and not configured to restart.
+    Exited,
+    /// Stopped on request.
+    Stopped,
+}
+
+impl ServiceState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServiceState::Waiting => "waiting",
+            ServiceState::Delaying => "delaying",
+            ServiceState::Starting => "starting",
+            ServiceState::Ready => "ready",
+            ServiceState::Crashed => "crashed",
+            ServiceState::Exited => "exited",
+            ServiceState::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceStatus {
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    /// `None` when restarts are unlimited, `Some(0)` when the service is not
+    /// restarted at all.
+    pub max_restarts: Option<u32>,
+    /// When the current process was spawned.
+    pub started_at: Option<DateTime<Local>>,
+    /// Readiness triggers the current process has not printed yet.
+    pub pending_triggers: Vec<String>,
+    /// CPU and resident memory of the process and all its descendants,
+    /// filled in when the status is requested.
+    pub cpu_percent: Option<f32>,
+    pub rss_bytes: Option<u64>,
+}
+
+/// Current state of every service in a running stack, shared between the
+/// service tasks and the control socket.
+#[derive(Clone, Default)]
+pub struct StatusBoard {
+    services: Arc<RwLock<BTreeMap<String, ServiceStatus>>>,
+}
+
+impl StatusBoard {
+    /// `services` are names with their `effective_max_restarts`.
+    pub fn new<'a>(services: impl Iterator<Item = (&'a String, u32)>) -> Self {
+        let services = services
+            .map(|(name, max_restarts)| {
+                let status = ServiceStatus {
+                    state: ServiceState::Waiting,
+                    pid: None,
+                    restarts: 0,
+                    max_restarts: (max_restarts != u32::MAX).then_some(max_restarts),
+                    started_at: None,
+                    pending_triggers: Vec::new(),
+                    cpu_percent: None,
+                    rss_bytes: None,
+                };
+                (name.clone(), status)
+            })
+            .collect();
+        StatusBoard {
+            services: Arc::new(RwLock::new(services)),
+        }
+    }
+
+    pub fn set(&self, name: &str, state: ServiceState, pid: Option<u32>, restarts: u32) {
+        let mut services = self.services.write().expect("lock poisoned");
+        let Some(status) = services.get_mut(name) else {
+            return;
+        };
+        if pid.is_none() {
+            status.started_at = None;
+            status.pending_triggers.clear();
+        } else if status.pid != pid {
+            status.started_at = Some(Local::now());
+        }
+        status.state = state;
+        status.pid = pid;
+        status.restarts = restarts;
+    }
+
+    pub fn set_pending_triggers(&self, name: &str, triggers: Vec<String>) {
+        let mut services = self.services.write().expect("lock poisoned");
+        if let Some(status) = services.get_mut(name) {
+            status.pending_triggers = triggers;
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ServiceStatus> {
+        self.services.read().expect("lock poisoned").clone()
+    }
+
+    /// Like `snapshot`, with CPU and memory usage of every running service.
+    /// Blocks for a moment, since CPU usage is measured between two samples.
+    pub fn snapshot_with_usage(&self) -> BTreeMap<String, ServiceStatus> {
+        let mut services = self.snapshot();
+        let pids: Vec<u32> = services.values().filter_map(|s| s.pid).collect();
+        let usage = process_tree_usage(&pids);
+        for status in services.values_mut() {
+            if let Some((cpu, rss)) = status.pid.and_then(|pid| usage.get(&pid)) {
+                status.cpu_percent = Some(*cpu);
+                status.rss_bytes = Some(*rss);
+            }
+        }
+        services
+    }
+}
+
+/// CPU (percent of one core) and resident memory (bytes) of each of `roots`
+/// together with all of its descendants.
+fn process_tree_usage(roots: &[u32]) -> HashMap<u32, (f32, u64)> {
+    let refresh = ProcessRefreshKind::nothing().with_cpu().with_memory();
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+    std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+
+    let mut usage: HashMap<u32, (f32, u64)> = roots.iter().map(|pid| (*pid, (0.0, 0))).collect();
+    for (pid, process) in system.processes() {
+        // Walk up the parents until one of the roots (or the top) is reached.
+        let mut current = Some(*pid);
+        while let Some(p) = current {
+            if let Some((cpu, rss)) = usage.get_mut(&p.as_u32()) {
+                *cpu += process.cpu_usage();
+                *rss += process.memory();
+                break;
+            }
+            current = system.process(p).and_then(|p| p.parent());
+        }
+    }
+    usage.retain(|pid, _| system.process(Pid::from_u32(*pid)).is_some());
+    usage
+}
@@ -1,36 +1,344 @@
-use anyhow::{bail, Context, Result};
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet, VecDeque};
+use crate::color::parse_color;
+use crate::diagnostic::{
+    display_path, locate, offset_of, Diagnostic, Diagnostics, PathSegment, Severity,
+};
+use crate::prefix::{check_time_format, PrefixTemplate, DEFAULT_PREFIX, DEFAULT_TIME_FORMAT};
+use crate::procfile::parse_procfile;
+use crate::util::closest_match;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-pub fn read_config(filepath: &str) -> Result<StepnConfig> {
-    let content =
-        std::fs::read_to_string(filepath).with_context(|| format!("{} not found", filepath))?;
-    let config: StepnConfig =
-        toml::from_str(&content).with_context(|| format!("failed to parse {}", filepath))?;
-    config.validate()?;
-    Ok(config)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+    Procfile,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+impl ConfigFormat {
+    /// Detects the format from the file name (`Procfile`, `Procfile.dev`, ...)
+    /// or extension, falling back to TOML.
+    pub fn from_path(filepath: &str) -> Self {
+        let path = Path::new(filepath);
+        if path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n == "Procfile" || n.starts_with("Procfile."))
+        {
+            return ConfigFormat::Procfile;
+        }
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+pub fn read_config(filepath: &str) -> Result<StepnConfig, Diagnostics> {
+    let (config, content) = load_config(filepath)?;
+    let issues = config.issues();
+    if issues.is_empty() {
+        return Ok(config);
+    }
+    Err(issue_diagnostics(
+        filepath,
+        content,
+        issues,
+        Severity::Error,
+    ))
+}
+
+/// Resolves each issue's key path to a source span for rendering.
+pub fn issue_diagnostics(
+    filepath: &str,
+    content: String,
+    issues: Vec<ConfigIssue>,
+    severity: Severity,
+) -> Diagnostics {
+    let format = ConfigFormat::from_path(filepath);
+    let items = issues
+        .into_iter()
+        .map(|issue| Diagnostic {
+            severity,
+            span: locate(&content, format, &issue.path),
+            path: Some(display_path(&issue.path)),
+            message: issue.message,
+            help: issue.help,
+        })
+        .collect();
+    Diagnostics {
+        filepath: filepath.to_string(),
+        content,
+        items,
+    }
+}
+
+/// Reads and parses the config without checking dependencies, for commands
+/// that want to show a broken graph rather than refuse to run.
+pub fn read_config_unvalidated(filepath: &str) -> Result<StepnConfig, Diagnostics> {
+    load_config(filepath).map(|(config, _)| config)
+}
+
+fn load_config(filepath: &str) -> Result<(StepnConfig, String), Diagnostics> {
+    let content = std::fs::read_to_string(filepath).map_err(|_| Diagnostics {
+        filepath: filepath.to_string(),
+        content: String::new(),
+        items: vec![Diagnostic::new(format!("{} not found", filepath))],
+    })?;
+    match parse_config(&content, ConfigFormat::from_path(filepath)) {
+        Ok(config) => Ok((config, content)),
+        Err(diagnostic) => Err(Diagnostics {
+            filepath: filepath.to_string(),
+            content,
+            items: vec![diagnostic],
+        }),
+    }
+}
+
+pub fn parse_config(content: &str, format: ConfigFormat) -> Result<StepnConfig, Diagnostic> {
+    let mut diagnostic = match format {
+        ConfigFormat::Toml => match toml::from_str(content) {
+            Ok(config) => return Ok(config),
+            Err(e) => Diagnostic {
+                severity: Severity::Error,
+                message: e.message().to_string(),
+                span: e.span(),
+                path: None,
+                help: None,
+            },
+        },
+        ConfigFormat::Yaml => match serde_norway::from_str(content) {
+            Ok(config) => return Ok(config),
+            Err(e) => {
+                let message = e.to_string();
+                match e.location() {
+                    Some(loc) => Diagnostic {
+                        severity: Severity::Error,
+                        message: strip_location(&message).to_string(),
+                        span: Some(word_span(content, loc.index())),
+                        path: None,
+                        help: None,
+                    },
+                    None => Diagnostic::new(message),
+                }
+            }
+        },
+        ConfigFormat::Json => match serde_json::from_str(content) {
+            Ok(config) => return Ok(config),
+            Err(e) => {
+                let message = e.to_string();
+                match offset_of(content, e.line(), e.column()) {
+                    Some(offset) if e.line() > 0 => Diagnostic {
+                        severity: Severity::Error,
+                        message: strip_location(&message).to_string(),
+                        span: Some(offset..offset + 1),
+                        path: None,
+                        help: None,
+                    },
+                    _ => Diagnostic::new(message),
+                }
+            }
+        },
+        ConfigFormat::Procfile => match parse_procfile(content) {
+            Ok(config) => return Ok(config),
+            Err(e) => Diagnostic::new(e.to_string()),
+        },
+    };
+    diagnostic.help = unknown_field_hint(&diagnostic.message);
+    Err(diagnostic)
+}
+
+/// Widens a position reported by the YAML parser to the key or word starting there.
+fn word_span(content: &str, offset: usize) -> Range<usize> {
+    let len = content
+        .get(offset..)
+        .unwrap_or_default()
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .unwrap_or(0);
+    offset..offset + len.max(1)
+}
+
+/// serde_norway and serde_json append " at line X column Y" to their messages;
+/// the rendered snippet already shows that.
+fn strip_location(message: &str) -> &str {
+    message
+        .rsplit_once(" at line ")
+        .map(|(m, _)| m)
+        .unwrap_or(message)
+}
+
+/// Suggests the closest valid key for serde's
+/// "unknown field `x`, expected one of `a`, `b`" errors.
+fn unknown_field_hint(message: &str) -> Option<String> {
+    const MARKER: &str = "unknown field `";
+    let rest = &message[message.find(MARKER)? + MARKER.len()..];
+    let (field, rest) = rest.split_once('`')?;
+    let expected: Vec<&str> = rest.split('`').skip(1).step_by(2).collect();
+    closest_match(field, &expected).map(|key| format!("did you mean `{}`?", key))
+}
+
+fn serialize_sorted<S, V>(map: &HashMap<String, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+fn serialize_sorted_opt<S, V>(
+    map: &Option<HashMap<String, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    V: Serialize,
+{
+    match map {
+        Some(map) => serialize_sorted(map, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
 pub struct StepnConfig {
+    /// Directory for per-service log files (`<log_dir>/<service>.log`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_rotation: Option<LogRotation>,
+    /// Prefix template for every output line, e.g. `"[{time}] {name} | "`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_prefix: Option<String>,
+    /// strftime-style format used for `{time}` in `log_prefix`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>,
+    /// Where services' stderr goes unless they set their own `stderr`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<StderrMode>,
+    /// Recent output lines kept per service and printed when it crashes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_lines: Option<usize>,
+    #[serde(serialize_with = "serialize_sorted")]
     pub services: HashMap<String, Service>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LogRotation {
+    /// Rotate once the file grows past this size.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    /// Rotate when the hour or day (UTC) changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<RotationInterval>,
+    /// Number of rotated files to keep next to the active one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StderrMode {
+    /// Print stderr lines on stepn's stdout, marked `*stderr*`.
+    #[default]
+    Merge,
+    /// Print stderr lines on stepn's stderr.
+    Separate,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Service {
     pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub depends_on: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub health_checker: Option<HealthChecker>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted_opt"
+    )]
     pub environments: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub delay_sec: Option<u64>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub restart: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_restarts: Option<u32>,
+    /// Log file for this service, overriding the `log_dir` default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<String>,
+    /// Prefix template for this service's lines, overriding the global one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_prefix: Option<String>,
+    /// Label colour, e.g. `cyan` or `bright_blue`; picked from a palette if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Where this service's stderr goes, overriding the global `stderr`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<StderrMode>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct HealthChecker {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub output_trigger: Option<Vec<String>>,
+    /// Probe run with `sh -c` once the service has started, until it exits 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Seconds between probe runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_sec: Option<u64>,
+}
+
+pub const DEFAULT_PROBE_INTERVAL_SEC: u64 = 1;
+
+impl HealthChecker {
+    /// Whether anything besides the first output line decides readiness.
+    pub fn has_signal(&self) -> bool {
+        self.output_trigger.as_ref().is_some_and(|t| !t.is_empty()) || self.command.is_some()
+    }
+}
+
+/// A semantic problem in an otherwise well-formed config, located by key path.
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    pub message: String,
+    pub path: Vec<PathSegment>,
+    pub help: Option<String>,
+}
+
+/// Path to a service's field, e.g. `services.web.delay_sec`.
+pub fn service_field_path(name: &str, field: &str) -> Vec<PathSegment> {
+    vec![
+        PathSegment::Key("services".to_string()),
+        PathSegment::Key(name.to_string()),
+        PathSegment::Key(field.to_string()),
+    ]
+}
+
+pub fn depends_on_path(name: &str, index: usize) -> Vec<PathSegment> {
+    let mut path = service_field_path(name, "depends_on");
+    path.push(PathSegment::Index(index));
+    path
 }
 
 impl Service {
@@ -47,58 +355,205 @@ impl Service {
 }
 
 impl StepnConfig {
-    fn validate(&self) -> Result<()> {
-        let service_names: HashSet<&str> = self.services.keys().map(|s| s.as_str()).collect();
-
-        for (name, service) in &self.services {
-            if let Some(deps) = &service.depends_on {
-                for dep in deps {
-                    if !service_names.contains(dep.as_str()) {
-                        bail!(
-                            "service '{}' depends on '{}', which is not defined",
-                            name,
-                            dep
-                        );
-                    }
+    /// Where the service's output is logged, if anywhere.
+    pub fn log_path(&self, name: &str) -> Option<PathBuf> {
+        match (&self.services.get(name)?.log_file, &self.log_dir) {
+            (Some(file), _) => Some(PathBuf::from(file)),
+            (None, Some(dir)) => Some(Path::new(dir).join(format!("{}.log", name))),
+            (None, None) => None,
+        }
+    }
+
+    pub fn log_prefix(&self, name: &str) -> &str {
+        self.services
+            .get(name)
+            .and_then(|s| s.log_prefix.as_deref())
+            .or(self.log_prefix.as_deref())
+            .unwrap_or(DEFAULT_PREFIX)
+    }
+
+    pub fn stderr_mode(&self, name: &str) -> StderrMode {
+        self.services
+            .get(name)
+            .and_then(|s| s.stderr)
+            .or(self.stderr)
+            .unwrap_or_default()
+    }
+
+    pub fn time_format(&self) -> &str {
+        self.time_format.as_deref().unwrap_or(DEFAULT_TIME_FORMAT)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let issues = self.issues();
+        if !issues.is_empty() {
+            bail!(
+                "{}",
+                issues
+                    .iter()
+                    .map(|i| i.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// Collects every problem in the config instead of stopping at the first one.
+    pub fn issues(&self) -> Vec<ConfigIssue> {
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+        let mut issues = Vec::new();
+
+        if let Some(Err(message)) = self.log_prefix.as_deref().map(PrefixTemplate::parse) {
+            issues.push(ConfigIssue {
+                message,
+                path: vec![PathSegment::Key("log_prefix".to_string())],
+                help: None,
+            });
+        }
+        if let Some(Err(message)) = self.time_format.as_deref().map(check_time_format) {
+            issues.push(ConfigIssue {
+                message,
+                path: vec![PathSegment::Key("time_format".to_string())],
+                help: Some("see https://docs.rs/chrono/latest/chrono/format/strftime/".to_string()),
+            });
+        }
+        for name in &names {
+            let prefix = self.services[*name].log_prefix.as_deref();
+            if let Some(Err(message)) = prefix.map(PrefixTemplate::parse) {
+                issues.push(ConfigIssue {
+                    message,
+                    path: service_field_path(name, "log_prefix"),
+                    help: None,
+                });
+            }
+            let color = self.services[*name].color.as_deref();
+            if let Some(Err(message)) = color.map(parse_color) {
+                issues.push(ConfigIssue {
+                    message,
+                    path: service_field_path(name, "color"),
+                    help: None,
+                });
+            }
+            let health_checker = self.services[*name].health_checker.as_ref();
+            if health_checker.and_then(|hc| hc.interval_sec) == Some(0) {
+                let mut path = service_field_path(name, "health_checker");
+                path.push(PathSegment::Key("interval_sec".to_string()));
+                issues.push(ConfigIssue {
+                    message: format!("service '{}' has a probe interval of 0 seconds", name),
+                    path,
+                    help: Some("use at least 1".to_string()),
+                });
+            }
+        }
+
+        // Two services appending to one file would rotate it over each other.
+        let mut log_owners: HashMap<PathBuf, &str> = HashMap::new();
+        for name in &names {
+            let Some(path) = self.log_path(name) else {
+                continue;
+            };
+            match log_owners.get(&path) {
+                Some(owner) => issues.push(ConfigIssue {
+                    message: format!(
+                        "services '{}' and '{}' both log to {}",
+                        owner,
+                        name,
+                        path.display()
+                    ),
+                    path: match self.services[*name].log_file {
+                        Some(_) => service_field_path(name, "log_file"),
+                        None => vec![PathSegment::Key("log_dir".to_string())],
+                    },
+                    help: Some("give each service its own log_file".to_string()),
+                }),
+                None => {
+                    log_owners.insert(path, name);
                 }
             }
         }
 
-        // Detect circular dependencies via DFS
-        let mut visited = HashSet::new();
-        let mut in_stack = HashSet::new();
-        for name in self.services.keys() {
-            if !visited.contains(name.as_str()) {
-                self.detect_cycle(name, &mut visited, &mut in_stack)?;
+        for name in &names {
+            if let Some(deps) = &self.services[*name].depends_on {
+                for (i, dep) in deps.iter().enumerate() {
+                    if !self.services.contains_key(dep) {
+                        issues.push(ConfigIssue {
+                            message: format!(
+                                "service '{}' depends on '{}', which is not defined",
+                                name, dep
+                            ),
+                            path: depends_on_path(name, i),
+                            help: None,
+                        });
+                    }
+                }
             }
         }
 
-        Ok(())
+        for cycle in self.cycles() {
+            // Point at the edge that closes the loop back to the first service.
+            let last = &cycle[cycle.len() - 1];
+            let index = self.services[last]
+                .depends_on
+                .iter()
+                .flatten()
+                .position(|d| *d == cycle[0])
+                .unwrap_or(0);
+            issues.push(ConfigIssue {
+                message: format!(
+                    "circular dependency detected: {} -> {}",
+                    cycle.join(" -> "),
+                    cycle[0]
+                ),
+                path: depends_on_path(last, index),
+                help: None,
+            });
+        }
+
+        issues
     }
 
-    fn detect_cycle<'a>(
+    /// Every distinct dependency cycle, each rotated to start at its alphabetically
+    /// first service: `["cache", "web", "worker"]` means cache -> web -> worker -> cache.
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        let mut names: Vec<&str> = self.services.keys().map(|s| s.as_str()).collect();
+        names.sort();
+        let mut cycles = Vec::new();
+        for start in names {
+            let mut path = vec![start];
+            self.find_cycles(start, &mut path, &mut cycles);
+        }
+        cycles
+    }
+
+    fn find_cycles<'a>(
         &'a self,
-        node: &'a str,
-        visited: &mut HashSet<&'a str>,
-        in_stack: &mut HashSet<&'a str>,
-    ) -> Result<()> {
-        visited.insert(node);
-        in_stack.insert(node);
-
-        if let Some(service) = self.services.get(node) {
-            if let Some(deps) = &service.depends_on {
-                for dep in deps {
-                    if !visited.contains(dep.as_str()) {
-                        self.detect_cycle(dep, visited, in_stack)?;
-                    } else if in_stack.contains(dep.as_str()) {
-                        bail!("circular dependency detected: {} -> {}", node, dep);
-                    }
-                }
+        start: &str,
+        path: &mut Vec<&'a str>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        let node = path[path.len() - 1];
+        let mut seen = HashSet::new();
+        for dep in self.services[node].depends_on.iter().flatten() {
+            let dep = dep.as_str();
+            if !seen.insert(dep) {
+                continue;
+            }
+            if dep == start {
+                cycles.push(path.iter().map(|s| s.to_string()).collect());
+            } else if dep > start && !path.contains(&dep) && self.services.contains_key(dep) {
+                // Only visit services after `start` so each cycle is found once,
+                // from its smallest member.
+                path.push(dep);
+                self.find_cycles(start, path, cycles);
+                path.pop();
             }
         }
-
-        in_stack.remove(node);
-        Ok(())
     }
 
     pub fn resolve_transitive_deps(&self, names: &[String]) -> HashSet<String> {
@@ -135,3 +590,76 @@ impl StepnConfig {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> StepnConfig {
+        parse_config(toml, ConfigFormat::Toml).expect("valid config")
+    }
+
+    #[test]
+    fn cycles_are_found_once_from_their_smallest_member() {
+        let config = config(
+            r#"
+            [services.web]
+            command = "true"
+            depends_on = ["worker"]
+            [services.worker]
+            command = "true"
+            depends_on = ["cache"]
+            [services.cache]
+            command = "true"
+            depends_on = ["web"]
+            [services.db]
+            command = "true"
+            depends_on = ["db"]
+            "#,
+        );
+        assert_eq!(
+            config.cycles(),
+            vec![
+                vec!["cache".to_string(), "web".to_string(), "worker".to_string()],
+                vec!["db".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn acyclic_config_has_no_cycles() {
+        let config = config(
+            r#"
+            [services.a]
+            command = "true"
+            [services.b]
+            command = "true"
+            depends_on = ["a", "a", "missing"]
+            [services.c]
+            command = "true"
+            depends_on = ["a", "b"]
+            "#,
+        );
+        assert!(config.cycles().is_empty());
+    }
+
+    #[test]
+    fn services_sharing_a_log_file_are_rejected() {
+        let config = config(
+            r#"
+            log_dir = "logs"
+            [services.web]
+            command = "true"
+            log_file = "logs/api.log"
+            [services.api]
+            command = "true"
+            "#,
+        );
+        let issues = config.issues();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].message,
+            "services 'api' and 'web' both log to logs/api.log"
+        );
+    }
+}
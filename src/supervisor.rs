@@ -0,0 +1,754 @@
+use crate::history::{History, RecordedLine};
+use crate::line_reader::LineReader;
+use crate::log_file::{LogFile, LogWriter};
+use crate::output::{Event, Output, ServiceOutput};
+use crate::status::{ServiceState, StatusBoard};
+use crate::stepn_config::{Service, StepnConfig, DEFAULT_PROBE_INTERVAL_SEC};
+use nix::sys::signal::{killpg, Signal};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio_stream::StreamExt;
+
+pub const SHELL: &str = "sh";
+
+/// How long a stopped service gets to exit after SIGTERM before it is killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment passed to a service's process on top of stepn's own, sorted by key.
+pub fn service_env(service: &Service) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::from([("IS_STEPN".to_string(), "true".to_string())]);
+    if let Some(vars) = &service.environments {
+        env.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    env
+}
+
+/// A request to a running service actor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// Start the service if it is stopped or has exited.
+    Start,
+    Stop,
+    /// Stop the service if it runs, then start it again once its
+    /// dependencies are ready.
+    Restart,
+}
+
+/// Whether an actor still has work to do, used to tell when `run` is over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Activity {
+    Active,
+    /// Exited on its own and will not be restarted.
+    Finished,
+    /// Stopped on request; waits for `Start`.
+    Stopped,
+}
+
+/// State shared by every service actor of one stack.
+struct Shared {
+    config: &'static StepnConfig,
+    output: Output,
+    history: Arc<History>,
+    board: StatusBoard,
+    label_width: usize,
+    ready: RwLock<HashMap<String, bool>>,
+    children: Arc<RwLock<Vec<u32>>>,
+    activity: Mutex<HashMap<String, Activity>>,
+    changed: Notify,
+}
+
+impl Shared {
+    fn is_ready(&self, name: &str) -> bool {
+        *self
+            .ready
+            .read()
+            .expect("lock poisoned")
+            .get(name)
+            .unwrap_or(&false)
+    }
+
+    fn set_ready(&self, name: &str, ready: bool) {
+        self.ready
+            .write()
+            .expect("lock poisoned")
+            .insert(name.to_string(), ready);
+    }
+
+    fn set_activity(&self, name: &str, activity: Activity) {
+        self.activity
+            .lock()
+            .expect("lock poisoned")
+            .insert(name.to_string(), activity);
+        self.changed.notify_one();
+    }
+
+    fn activity(&self, name: &str) -> Activity {
+        self.activity.lock().expect("lock poisoned")[name]
+    }
+}
+
+/// What `Supervisor::start` did with the requested services.
+#[derive(Default)]
+pub struct StartOutcome {
+    /// Services asked to start, dependencies included.
+    pub started: Vec<String>,
+    /// Requested services that were still running and left alone.
+    pub running: Vec<String>,
+}
+
+impl StartOutcome {
+    /// e.g. `starting db, web; cache is already running`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.started.is_empty() {
+            parts.push(format!("starting {}", self.started.join(", ")));
+        }
+        parts.extend(
+            self.running
+                .iter()
+                .map(|name| format!("{} is already running", name)),
+        );
+        parts.join("; ")
+    }
+}
+
+/// Runs every service of a stack as an actor that can be started, stopped and
+/// restarted while the others keep running.
+pub struct Supervisor {
+    shared: Arc<Shared>,
+    actors: BTreeMap<String, UnboundedSender<Control>>,
+}
+
+impl Supervisor {
+    pub fn spawn(
+        config: &'static StepnConfig,
+        names: &[String],
+        output: Output,
+        history: Arc<History>,
+        board: StatusBoard,
+        label_width: usize,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            config,
+            output,
+            history,
+            board,
+            label_width,
+            ready: RwLock::new(names.iter().map(|n| (n.clone(), false)).collect()),
+            children: Arc::new(RwLock::new(Vec::new())),
+            activity: Mutex::new(
+                names
+                    .iter()
+                    .map(|n| (n.clone(), Activity::Active))
+                    .collect(),
+            ),
+            changed: Notify::new(),
+        });
+        let actors = names
+            .iter()
+            .map(|name| {
+                let (tx, rx) = unbounded_channel();
+                let (name, _) = config
+                    .services
+                    .get_key_value(name)
+                    .expect("service names are checked before spawning");
+                tokio::spawn(run_actor(Arc::clone(&shared), name, rx));
+                (name.clone(), tx)
+            })
+            .collect();
+        Supervisor { shared, actors }
+    }
+
+    /// PIDs of the services' running processes (each leads its own process group).
+    pub fn children(&self) -> Arc<RwLock<Vec<u32>>> {
+        Arc::clone(&self.shared.children)
+    }
+
+    /// Recent and live output of the services.
+    pub fn history(&self) -> Arc<History> {
+        Arc::clone(&self.shared.history)
+    }
+
+    /// Every service of this stack, sorted.
+    pub fn names(&self) -> Vec<String> {
+        self.actors.keys().cloned().collect()
+    }
+
+    /// Names not part of this stack, for error messages.
+    pub fn unknown<'a>(&self, names: &'a [String]) -> Vec<&'a str> {
+        names
+            .iter()
+            .filter(|n| !self.actors.contains_key(*n))
+            .map(|n| n.as_str())
+            .collect()
+    }
+
+    /// Services of this stack that depend on any of `names`, directly or not.
+    fn dependents(&self, names: &[String]) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+        let mut queue: Vec<String> = names.to_vec();
+        while let Some(name) = queue.pop() {
+            for dependent in self.shared.config.dependents_of(&name) {
+                if self.actors.contains_key(&dependent) && result.insert(dependent.clone()) {
+                    queue.push(dependent);
+                }
+            }
+        }
+        result
+    }
+
+    fn send(&self, name: &str, control: Control) {
+        if let Some(tx) = self.actors.get(name) {
+            let _ = tx.send(control);
+        }
+    }
+
+    /// Starts those of `names` that were stopped or have exited, along with
+    /// any dependency that was stopped on request.
+    pub fn start(&self, names: &[String]) -> StartOutcome {
+        let mut outcome = StartOutcome::default();
+        let mut targets: Vec<String> = self
+            .shared
+            .config
+            .resolve_transitive_deps(names)
+            .into_iter()
+            .filter(|n| self.actors.contains_key(n))
+            .collect();
+        targets.sort();
+        for name in targets {
+            let activity = self.shared.activity(&name);
+            if activity == Activity::Stopped
+                || (names.contains(&name) && activity == Activity::Finished)
+            {
+                self.send(&name, Control::Start);
+                outcome.started.push(name);
+            } else if names.contains(&name) {
+                outcome.running.push(name);
+            }
+        }
+        outcome
+    }
+
+    /// Stops `names`, and with `cascade` everything that depends on them.
+    pub fn stop(&self, names: &[String], cascade: bool) -> Vec<String> {
+        self.broadcast(names, cascade, Control::Stop)
+    }
+
+    /// Restarts `names`, and with `cascade` everything that depends on them.
+    pub fn restart(&self, names: &[String], cascade: bool) -> Vec<String> {
+        self.broadcast(names, cascade, Control::Restart)
+    }
+
+    fn broadcast(&self, names: &[String], cascade: bool, control: Control) -> Vec<String> {
+        let mut targets: BTreeSet<String> = names.iter().cloned().collect();
+        if cascade {
+            targets.extend(self.dependents(names));
+        }
+        for name in &targets {
+            // Dependents restarted alongside must not see the old readiness.
+            self.shared.set_ready(name, false);
+            self.send(name, control);
+        }
+        targets.into_iter().collect()
+    }
+
+    /// Resolves once every service has exited for good. A service stopped on
+    /// request keeps the stack alive, since it may be started again.
+    pub async fn finished(&self) {
+        loop {
+            let done = self
+                .shared
+                .activity
+                .lock()
+                .expect("lock poisoned")
+                .values()
+                .all(|a| *a == Activity::Finished);
+            if done {
+                return;
+            }
+            self.shared.changed.notified().await;
+        }
+    }
+}
+
+/// Per-process line handling: console, history, log file and readiness.
+struct Sink {
+    shared: Arc<Shared>,
+    name: &'static str,
+    out: ServiceOutput<'static>,
+    log_file: Option<LogWriter>,
+    pending_triggers: HashMap<String, bool>,
+    /// The health probe has not passed yet.
+    probe_pending: bool,
+    /// Set once the process was asked to stop, so its last lines don't mark
+    /// it ready again.
+    stopping: bool,
+}
+
+impl Sink {
+    async fn line(&mut self, is_stderr: bool, line: &str, pid: Option<u32>, restart: u32) {
+        let name = self.name;
+        let recorded = RecordedLine::now(is_stderr, line, pid, restart);
+        self.out.line(&recorded).await;
+        self.shared.history.push(name, recorded);
+        if let Some(file) = &mut self.log_file {
+            file.write_line(is_stderr, line);
+        }
+
+        if self.pending_triggers.values().any(|done| !done) {
+            let mut matched = false;
+            for (keyword, done) in self.pending_triggers.iter_mut() {
+                if !*done && line.contains(keyword.as_str()) {
+                    *done = true;
+                    matched = true;
+                }
+            }
+            if matched {
+                self.publish_pending_triggers();
+                // The last trigger may be the last line the service prints.
+                if !self.probe_pending && self.pending_triggers.values().all(|done| *done) {
+                    self.mark_ready(pid, restart);
+                }
+            }
+        } else if !self.probe_pending {
+            self.mark_ready(pid, restart);
+        }
+    }
+
+    /// Called once the health probe exits 0.
+    fn probe_passed(&mut self, pid: Option<u32>, restart: u32) {
+        self.probe_pending = false;
+        self.publish_pending_triggers();
+        if self.pending_triggers.values().all(|done| *done) {
+            self.mark_ready(pid, restart);
+        }
+    }
+
+    fn mark_ready(&mut self, pid: Option<u32>, restart: u32) {
+        let name = self.name;
+        if self.stopping || self.shared.is_ready(name) {
+            return;
+        }
+        self.shared.set_ready(name, true);
+        self.shared
+            .board
+            .set(name, ServiceState::Ready, pid, restart);
+        self.out.event(Event::Ready, pid, restart);
+    }
+
+    fn publish_pending_triggers(&self) {
+        let mut pending: Vec<String> = self
+            .pending_triggers
+            .iter()
+            .filter(|(_, done)| !**done)
+            .map(|(keyword, _)| keyword.clone())
+            .collect();
+        pending.sort();
+        if self.probe_pending {
+            pending.push("probe".to_string());
+        }
+        self.shared.board.set_pending_triggers(self.name, pending);
+    }
+}
+
+fn triggers(service: &Service) -> HashMap<String, bool> {
+    service
+        .health_checker
+        .as_ref()
+        .and_then(|hc| hc.output_trigger.as_ref())
+        .map(|triggers| triggers.iter().map(|t| (t.clone(), false)).collect())
+        .unwrap_or_default()
+}
+
+/// Runs the service's health probe every interval until it exits 0.
+async fn probe(command: String, interval: Duration, env: BTreeMap<String, String>) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let status = Command::new(SHELL)
+            .arg("-c")
+            .arg(&command)
+            .envs(&env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await;
+        if status.is_ok_and(|status| status.success()) {
+            return;
+        }
+    }
+}
+
+/// Sleeps for `duration` unless a request other than `Start` arrives first.
+async fn pause(duration: Duration, control: &mut UnboundedReceiver<Control>) -> Option<Control> {
+    let sleep = tokio::time::sleep(duration);
+    tokio::pin!(sleep);
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return None,
+            Some(request) = control.recv() => {
+                if request != Control::Start {
+                    return Some(request);
+                }
+            }
+        }
+    }
+}
+
+/// Waits for every dependency to become ready. Returns a request that
+/// interrupted the wait, if any.
+async fn wait_for_dependencies(
+    shared: &Shared,
+    name: &'static str,
+    service: &Service,
+    out: &mut ServiceOutput<'static>,
+    control: &mut UnboundedReceiver<Control>,
+) -> Option<Control> {
+    for dep in service.depends_on.iter().flatten() {
+        loop {
+            shared.board.set(name, ServiceState::Waiting, None, 0);
+            out.event(Event::Waiting { dependency: dep }, None, 0);
+            if let Some(request) = pause(Duration::from_secs(1), control).await {
+                return Some(request);
+            }
+            if shared.is_ready(dep) {
+                break;
+            }
+        }
+    }
+    None
+}
+
+async fn run_actor(
+    shared: Arc<Shared>,
+    name: &'static str,
+    mut control: UnboundedReceiver<Control>,
+) {
+    let config = shared.config;
+    let service = &config.services[name];
+    let out = ServiceOutput::for_service(shared.output.clone(), config, name, shared.label_width);
+    let log_file = config.log_path(name).and_then(|path| {
+        let output = shared.output.clone();
+        match LogFile::open(&path, config.log_rotation.as_ref()) {
+            Ok(file) => Some(file.spawn(shared.output.overflow(), move |e| {
+                let message = format!("{}: log file write failed, logging stopped: {}", name, e);
+                output.notice("log_file", &message);
+            })),
+            Err(e) => {
+                let message = format!("{}: cannot open log file {}: {}", name, path.display(), e);
+                output.notice("log_file", &message);
+                None
+            }
+        }
+    });
+    let mut sink = Sink {
+        shared: Arc::clone(&shared),
+        name,
+        out,
+        log_file,
+        pending_triggers: HashMap::new(),
+        probe_pending: false,
+        stopping: false,
+    };
+
+    loop {
+        shared.set_activity(name, Activity::Active);
+        let activity = match supervise(&shared, name, service, &mut sink, &mut control).await {
+            Ending::Restart => continue,
+            Ending::Stopped => Activity::Stopped,
+            Ending::Finished => Activity::Finished,
+        };
+        if !idle(&shared, name, &mut control, activity).await {
+            return;
+        }
+    }
+}
+
+/// Why a service stopped running.
+enum Ending {
+    /// Exited and will not be restarted.
+    Finished,
+    Stopped,
+    Restart,
+}
+
+/// Boots the service and keeps it running (restarting it after crashes if
+/// configured) until it is done or a request interrupts it.
+async fn supervise(
+    shared: &Shared,
+    name: &'static str,
+    service: &Service,
+    sink: &mut Sink,
+    control: &mut UnboundedReceiver<Control>,
+) -> Ending {
+    let interrupted = |request: Control, sink: &mut Sink, pid: Option<u32>, restart: u32| {
+        if request == Control::Stop {
+            sink.out.event(Event::Stopped, pid, restart);
+            Ending::Stopped
+        } else {
+            Ending::Restart
+        }
+    };
+
+    if let Some(request) =
+        wait_for_dependencies(shared, name, service, &mut sink.out, control).await
+    {
+        return interrupted(request, sink, None, 0);
+    }
+
+    if let Some(delay_sec) = service.delay_sec {
+        shared.board.set(name, ServiceState::Delaying, None, 0);
+        sink.out.event(Event::Delaying { secs: delay_sec }, None, 0);
+        if let Some(request) = pause(Duration::from_secs(delay_sec), control).await {
+            return interrupted(request, sink, None, 0);
+        }
+    }
+
+    let max_restarts = service.effective_max_restarts();
+    let mut restart_count: u32 = 0;
+    loop {
+        let (request, pid) = run_process(shared, name, service, sink, control, restart_count).await;
+        sink.out.report_dropped(pid, restart_count);
+        if let Some(request) = request {
+            shared.set_ready(name, false);
+            return interrupted(request, sink, pid, restart_count);
+        }
+
+        if !service.restart {
+            shared
+                .board
+                .set(name, ServiceState::Exited, None, restart_count);
+            return Ending::Finished;
+        }
+
+        shared.set_ready(name, false);
+        shared
+            .board
+            .set(name, ServiceState::Crashed, None, restart_count);
+        // Only this run's output; history also holds earlier runs.
+        let tail: Vec<RecordedLine> = shared
+            .history
+            .tail(name, usize::MAX)
+            .into_iter()
+            .filter(|line| line.pid == pid && line.restart == restart_count)
+            .collect();
+        if max_restarts != u32::MAX && restart_count >= max_restarts {
+            sink.out.event(Event::Crashed, pid, restart_count);
+            sink.out.crash_tail(&tail, pid, restart_count);
+            return Ending::Finished;
+        }
+
+        sink.out.event(
+            Event::Restarting {
+                attempt: restart_count + 1,
+                max: max_restarts,
+            },
+            pid,
+            restart_count,
+        );
+        sink.out.crash_tail(&tail, pid, restart_count);
+        restart_count += 1;
+
+        if let Some(request) = pause(Duration::from_secs(1), control).await {
+            return interrupted(request, sink, None, restart_count);
+        }
+    }
+}
+
+/// Parks a service that is not running until it is asked to start again.
+/// Returns false once the supervisor is gone.
+async fn idle(
+    shared: &Shared,
+    name: &'static str,
+    control: &mut UnboundedReceiver<Control>,
+    activity: Activity,
+) -> bool {
+    if activity == Activity::Stopped {
+        shared.board.set(name, ServiceState::Stopped, None, 0);
+    }
+    shared.set_activity(name, activity);
+    loop {
+        match control.recv().await {
+            Some(Control::Start) | Some(Control::Restart) => return true,
+            Some(Control::Stop) => {
+                shared.board.set(name, ServiceState::Stopped, None, 0);
+                shared.set_activity(name, Activity::Stopped);
+            }
+            None => return false,
+        }
+    }
+}
+
+/// Runs the service's command once, until it exits or a request interrupts it.
+async fn run_process(
+    shared: &Shared,
+    name: &'static str,
+    service: &Service,
+    sink: &mut Sink,
+    control: &mut UnboundedReceiver<Control>,
+    restart_count: u32,
+) -> (Option<Control>, Option<u32>) {
+    sink.pending_triggers = triggers(service);
+    let probe_command = service
+        .health_checker
+        .as_ref()
+        .and_then(|hc| hc.command.clone());
+    sink.probe_pending = probe_command.is_some();
+    sink.stopping = false;
+
+    let mut std_cmd = std::process::Command::new(SHELL);
+    std_cmd
+        .arg("-c")
+        .arg(&service.command)
+        .envs(service_env(service))
+        // stdin belongs to stepn's own commands (or the dashboard).
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so stopping the service also stops whatever
+        // the command spawned.
+        .process_group(0);
+    let mut cmd = Command::from(std_cmd);
+    cmd.kill_on_drop(true);
+
+    let mut child = cmd.spawn().unwrap_or_else(|e| {
+        panic!("failed to start command '{}': {}", service.command, e);
+    });
+
+    let stdout = child.stdout.take().expect("stdout not captured");
+    let stderr = child.stderr.take().expect("stderr not captured");
+    let pid = child.id();
+    if let Some(pid) = pid {
+        shared.children.write().expect("lock poisoned").push(pid);
+    }
+    shared
+        .board
+        .set(name, ServiceState::Starting, pid, restart_count);
+    sink.publish_pending_triggers();
+    sink.out.event(Event::Started, pid, restart_count);
+
+    let stdout_reader = Box::pin(LineReader::new(stdout).into_stream());
+    let stderr_reader = Box::pin(LineReader::new(stderr).into_stream());
+    let mut merged_stream = stdout_reader
+        .map(|line| (false, line))
+        .merge(stderr_reader.map(|line| (true, line)));
+
+    let mut probe_task = probe_command.map(|command| {
+        let interval = service
+            .health_checker
+            .as_ref()
+            .and_then(|hc| hc.interval_sec)
+            .unwrap_or(DEFAULT_PROBE_INTERVAL_SEC);
+        tokio::spawn(probe(
+            command,
+            Duration::from_secs(interval),
+            service_env(service),
+        ))
+    });
+
+    let mut interrupted = None;
+    loop {
+        tokio::select! {
+            result = async { probe_task.as_mut().expect("probe pending").await }, if probe_task.is_some() => {
+                // A finished JoinHandle must not be polled again.
+                probe_task = None;
+                match result {
+                    Ok(()) => sink.probe_passed(pid, restart_count),
+                    Err(e) => {
+                        let message = format!("{}: health probe failed: {}", name, e);
+                        shared.output.notice("probe", &message);
+                    }
+                }
+            },
+            item = merged_stream.next() => match item {
+                Some((is_stderr, line)) => sink.line(is_stderr, &line, pid, restart_count).await,
+                None => break,
+            },
+            Some(request) = control.recv(), if interrupted.is_none() => {
+                if request == Control::Start {
+                    continue;
+                }
+                interrupted = Some(request);
+                sink.stopping = true;
+                if let Some(pid) = pid {
+                    let _ = killpg(nix::unistd::Pid::from_raw(pid as i32), Signal::SIGTERM);
+                }
+                // Keep forwarding output while the service shuts down.
+                let drain = async {
+                    while let Some((is_stderr, line)) = merged_stream.next().await {
+                        sink.line(is_stderr, &line, pid, restart_count).await;
+                    }
+                };
+                if tokio::time::timeout(STOP_TIMEOUT, drain).await.is_err() {
+                    if let Some(pid) = pid {
+                        let _ = killpg(nix::unistd::Pid::from_raw(pid as i32), Signal::SIGKILL);
+                    }
+                }
+                break;
+            },
+        }
+    }
+    if let Some(task) = probe_task {
+        task.abort();
+    }
+    let _ = child.wait().await;
+
+    if let Some(pid) = pid {
+        shared
+            .children
+            .write()
+            .expect("lock poisoned")
+            .retain(|p| *p != pid);
+    }
+    (interrupted, pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Filter, LogFormat};
+    use crate::stepn_config::{parse_config, ConfigFormat};
+    use crate::writer::Overflow;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_trigger_on_the_last_line_marks_the_service_ready() {
+        let config = parse_config(
+            r#"
+            [services.web]
+            command = "echo booting; echo listening; sleep 5"
+            [services.web.health_checker]
+            output_trigger = ["listening"]
+            "#,
+            ConfigFormat::Toml,
+        )
+        .expect("valid config");
+        let config: &'static StepnConfig = Box::leak(Box::new(config));
+        let names = vec!["web".to_string()];
+        let board = StatusBoard::new(names.iter().map(|n| (n, 0)));
+        let output = Output::new(LogFormat::Text, Filter::default(), Overflow::Block);
+        let supervisor = Supervisor::spawn(
+            config,
+            &names,
+            output,
+            Arc::new(History::new(10)),
+            board.clone(),
+            3,
+        );
+
+        let ready = async {
+            while board.snapshot()["web"].state != ServiceState::Ready {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        let result = tokio::time::timeout(Duration::from_secs(3), ready).await;
+        supervisor.stop(&names, false);
+        assert!(result.is_ok(), "web never became ready");
+    }
+}
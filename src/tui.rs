@@ -0,0 +1,355 @@
+use crate::color::service_color;
+use crate::history::ServiceLine;
+use crate::status::{ServiceState, ServiceStatus, StatusBoard};
+use crate::stepn_config::StepnConfig;
+use crate::supervisor::Supervisor;
+use crate::util::pad_with_trailing_space;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::TryRecvError;
+
+/// Lines kept for the log pane, across all services.
+const SCROLLBACK_LINES: usize = 10_000;
+/// How often the screen is redrawn when nothing is pressed.
+const TICK: Duration = Duration::from_millis(100);
+
+const HELP: &str =
+    "↑↓ select  enter focus/all  r restart  x stop  s start  / search  PgUp/PgDn scroll  q quit";
+
+/// Why the dashboard closed.
+pub enum Exit {
+    /// The user asked to stop the stack.
+    Quit,
+    /// Every service finished on its own.
+    Finished,
+}
+
+enum Mode {
+    Normal,
+    /// Typing a search query.
+    Search,
+}
+
+struct App {
+    supervisor: Arc<Supervisor>,
+    board: StatusBoard,
+    names: Vec<String>,
+    label_width: usize,
+    colors: HashMap<String, Color>,
+    lines: VecDeque<ServiceLine>,
+    /// Lines lost because the dashboard fell behind.
+    skipped: u64,
+    selected: ListState,
+    /// Show only the selected service's output instead of all of it.
+    focused: bool,
+    /// Lines scrolled up from the bottom; 0 follows new output.
+    scroll: usize,
+    /// Height of the log pane at the last draw, for paging.
+    page: usize,
+    search: String,
+    mode: Mode,
+    /// Outcome of the last action, shown in the footer until the next key.
+    message: Option<String>,
+}
+
+/// Runs the dashboard on the current terminal until the user quits or `done`
+/// is set.
+pub fn run(
+    config: &'static StepnConfig,
+    supervisor: Arc<Supervisor>,
+    board: StatusBoard,
+    done: Arc<AtomicBool>,
+) -> io::Result<Exit> {
+    let names = supervisor.names();
+    let (recent, mut live) = supervisor.history().follow(&names, usize::MAX, None);
+    let colors = names
+        .iter()
+        .map(|name| (name.clone(), to_tui_color(service_color(config, name))))
+        .collect();
+    let mut app = App {
+        supervisor,
+        board,
+        label_width: names.iter().map(|n| n.len()).max().unwrap_or(0),
+        names,
+        colors,
+        lines: recent.into_iter().collect(),
+        skipped: 0,
+        selected: ListState::default().with_selected(Some(0)),
+        focused: false,
+        scroll: 0,
+        page: 0,
+        search: String::new(),
+        mode: Mode::Normal,
+        message: None,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| loop {
+        loop {
+            match live.try_recv() {
+                Ok(line) => app.push(line),
+                Err(TryRecvError::Lagged(n)) => app.skipped += n,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+            }
+        }
+        if done.load(Ordering::SeqCst) {
+            return Ok(Exit::Finished);
+        }
+        app.draw_to(&mut terminal)?;
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && app.key(key) {
+                    return Ok(Exit::Quit);
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+fn to_tui_color(color: colored::Color) -> Color {
+    use colored::Color as C;
+    match color {
+        C::Black => Color::Black,
+        C::Red => Color::Red,
+        C::Green => Color::Green,
+        C::Yellow => Color::Yellow,
+        C::Blue => Color::Blue,
+        C::Magenta => Color::Magenta,
+        C::Cyan => Color::Cyan,
+        C::White => Color::Gray,
+        C::BrightBlack => Color::DarkGray,
+        C::BrightRed => Color::LightRed,
+        C::BrightGreen => Color::LightGreen,
+        C::BrightYellow => Color::LightYellow,
+        C::BrightBlue => Color::LightBlue,
+        C::BrightMagenta => Color::LightMagenta,
+        C::BrightCyan => Color::LightCyan,
+        C::BrightWhite => Color::White,
+        C::TrueColor { r, g, b } => Color::Rgb(r, g, b),
+    }
+}
+
+fn state_color(state: ServiceState) -> Color {
+    match state {
+        ServiceState::Ready => Color::Green,
+        ServiceState::Crashed => Color::Red,
+        ServiceState::Exited | ServiceState::Stopped => Color::DarkGray,
+        _ => Color::Yellow,
+    }
+}
+
+impl App {
+    fn push(&mut self, line: ServiceLine) {
+        if self.lines.len() == SCROLLBACK_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        // Keep the view still while scrolled up.
+        if self.scroll > 0 && self.shows(self.lines.back().expect("just pushed")) {
+            self.scroll = self.scroll.saturating_add(1);
+        }
+    }
+
+    fn selected_name(&self) -> Option<&String> {
+        self.selected.selected().and_then(|i| self.names.get(i))
+    }
+
+    fn shows(&self, (service, line): &ServiceLine) -> bool {
+        (!self.focused || self.selected_name() == Some(service))
+            && (self.search.is_empty() || line.text.contains(&self.search))
+    }
+
+    /// Handles one key press. Returns true to quit.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        self.message = None;
+        if let Mode::Search = self.mode {
+            match key.code {
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Esc => {
+                    self.search.clear();
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            }
+            self.scroll = 0;
+            return false;
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Char('c') if ctrl => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Enter | KeyCode::Char('f') => {
+                self.focused = !self.focused;
+                self.scroll = 0;
+            }
+            KeyCode::Char('r') => self.control("restarting", Supervisor::restart),
+            KeyCode::Char('x') => self.control("stopping", Supervisor::stop),
+            KeyCode::Char('s') => {
+                if let Some(name) = self.selected_name().cloned() {
+                    self.message = Some(self.supervisor.start(&[name]).describe());
+                }
+            }
+            KeyCode::Char('/') => {
+                self.search.clear();
+                self.mode = Mode::Search;
+            }
+            KeyCode::Esc => self.search.clear(),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(self.page.max(1)),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.page.max(1)),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll = usize::MAX,
+            KeyCode::End | KeyCode::Char('G') => self.scroll = 0,
+            _ => {}
+        }
+        false
+    }
+
+    fn select(&mut self, delta: isize) {
+        let count = self.names.len() as isize;
+        if count == 0 {
+            return;
+        }
+        let current = self.selected.selected().unwrap_or(0) as isize;
+        self.selected
+            .select(Some((current + delta).rem_euclid(count) as usize));
+        if self.focused {
+            self.scroll = 0;
+        }
+    }
+
+    fn control(&mut self, verb: &str, action: fn(&Supervisor, &[String], bool) -> Vec<String>) {
+        if let Some(name) = self.selected_name().cloned() {
+            let affected = action(&self.supervisor, &[name], false);
+            self.message = Some(format!("{} {}", verb, affected.join(", ")));
+        }
+    }
+
+    fn draw_to(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let statuses = self.board.snapshot();
+        terminal.draw(|frame| self.draw(frame, &statuses))?;
+        Ok(())
+    }
+
+    fn draw(
+        &mut self,
+        frame: &mut Frame,
+        statuses: &std::collections::BTreeMap<String, ServiceStatus>,
+    ) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let list_width = (self.label_width + 16).min(main.width as usize / 2) as u16;
+        let [list_area, log_area] =
+            Layout::horizontal([Constraint::Length(list_width), Constraint::Min(0)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .names
+            .iter()
+            .map(|name| {
+                let status = &statuses[name];
+                let health = if status.state == ServiceState::Starting
+                    && !status.pending_triggers.is_empty()
+                {
+                    Span::styled(" …", Style::default().fg(Color::Yellow))
+                } else {
+                    Span::raw("")
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        pad_with_trailing_space(self.label_width, name),
+                        Style::default().fg(self.colors[name]),
+                    ),
+                    Span::raw(" "),
+                    Span::styled(
+                        status.state.as_str(),
+                        Style::default().fg(state_color(status.state)),
+                    ),
+                    health,
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" services "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.selected);
+
+        let height = log_area.height.saturating_sub(2) as usize;
+        self.page = height;
+        let visible: Vec<&ServiceLine> = self.lines.iter().filter(|l| self.shows(l)).collect();
+        self.scroll = self.scroll.min(visible.len().saturating_sub(height));
+        let end = visible.len() - self.scroll;
+        let start = end.saturating_sub(height);
+        let lines: Vec<Line> = visible[start..end]
+            .iter()
+            .map(|(service, line)| self.log_line(service, &line.text, line.is_stderr))
+            .collect();
+
+        let mut title = match (self.focused, self.selected_name()) {
+            (true, Some(name)) => format!(" {} ", name),
+            _ => " all services ".to_string(),
+        };
+        if !self.search.is_empty() {
+            title.push_str(&format!("/{} ", self.search));
+        }
+        if self.scroll > 0 {
+            title.push_str(&format!("[+{}] ", self.scroll));
+        }
+        if self.skipped > 0 {
+            title.push_str(&format!("({} lines skipped) ", self.skipped));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            log_area,
+        );
+
+        let footer_text = match (&self.mode, &self.message) {
+            (Mode::Search, _) => Line::from(format!("/{}▏", self.search)),
+            (Mode::Normal, Some(message)) => Line::from(message.as_str()),
+            (Mode::Normal, None) => Line::styled(HELP, Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    /// One line of the log pane, with search matches highlighted.
+    fn log_line<'a>(&self, service: &str, text: &'a str, is_stderr: bool) -> Line<'a> {
+        let text_style = if is_stderr {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        let mut spans = Vec::new();
+        if !self.focused {
+            spans.push(Span::styled(
+                format!("{} ", pad_with_trailing_space(self.label_width, service)),
+                Style::default().fg(self.colors[service]),
+            ));
+        }
+        let mut rest = text;
+        if !self.search.is_empty() {
+            let highlight = text_style.bg(Color::Yellow).fg(Color::Black);
+            while let Some(i) = rest.find(&self.search) {
+                spans.push(Span::styled(&rest[..i], text_style));
+                spans.push(Span::styled(&rest[i..i + self.search.len()], highlight));
+                rest = &rest[i + self.search.len()..];
+            }
+        }
+        spans.push(Span::styled(rest, text_style));
+        Line::from(spans)
+    }
+}
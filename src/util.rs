@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 pub(crate) fn pad_with_trailing_space(width: usize, src: &str) -> String {
     format!("{:<width$}", src, width = width)
 }
@@ -5,3 +7,97 @@ pub(crate) fn pad_with_trailing_space(width: usize, src: &str) -> String {
 pub(crate) fn compute_label_width(names: impl Iterator<Item = impl AsRef<str>>) -> usize {
     names.map(|n| n.as_ref().len()).max().unwrap_or(10).max(5)
 }
+
+/// Parses a duration such as `90s`, `5m`, `2h` or `1d`; a bare number is seconds.
+pub(crate) fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => input.split_at(i),
+        None => (input, "s"),
+    };
+    let secs_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(n) if secs_per_unit > 0 => n
+            .checked_mul(secs_per_unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration \"{}\" is too long", input)),
+        _ => Err(format!(
+            "invalid duration \"{}\" (expected e.g. 30s, 5m, 2h or 1d)",
+            input
+        )),
+    }
+}
+
+pub(crate) fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Returns the candidate closest to `input`, if any is close enough to be a likely typo
+/// or a truncated/extended form of it (`delay` for `delay_sec`).
+pub(crate) fn closest_match<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let threshold = (input.len() / 3).max(2);
+    candidates
+        .iter()
+        .map(|c| (levenshtein(input, c), *c))
+        .filter(|(d, c)| *d <= threshold || c.starts_with(input) || input.starts_with(c))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_accepts_units_and_bare_seconds() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration(" 2h "), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_bad_input_and_overflow() {
+        for input in ["", "s", "5x", "-5s", "1.5h", "5 m"] {
+            assert!(parse_duration(input).is_err(), "{:?} was accepted", input);
+        }
+        assert!(parse_duration(&format!("{}d", u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("command", "command"), 0);
+        assert_eq!(levenshtein("comand", "command"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn closest_match_suggests_typos_and_prefixes() {
+        let keys = ["command", "depends_on", "delay_sec", "restart"];
+        assert_eq!(closest_match("comand", &keys), Some("command"));
+        assert_eq!(closest_match("depend_on", &keys), Some("depends_on"));
+        assert_eq!(closest_match("delay", &keys), Some("delay_sec"));
+        assert_eq!(closest_match("colour", &keys), None);
+        assert_eq!(closest_match("anything", &[]), None);
+    }
+}
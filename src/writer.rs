@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::sync::Semaphore;
+
+/// Service lines that may be queued before the overflow policy kicks in.
+pub const BUFFER_LINES: usize = 1024;
+
+/// What happens to a service line when the writer has fallen behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for room, pausing the service's output (and its readiness matching).
+    Block,
+    /// Discard the oldest queued service lines.
+    DropOldest,
+    /// Discard new lines and report how many were lost once there is room again.
+    Summarise,
+}
+
+impl Overflow {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "summarise" | "summarize" => Ok(Overflow::Summarise),
+            other => Err(format!(
+                "unknown overflow policy \"{}\" (expected block, drop-oldest or summarise)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Stdout,
+    Stderr,
+}
+
+struct Record {
+    target: Target,
+    text: String,
+    /// Service lines count toward `BUFFER_LINES`; stepn's own messages don't,
+    /// so they are never dropped or held up.
+    counted: bool,
+}
+
+#[derive(Default)]
+struct State {
+    records: VecDeque<Record>,
+    counted: usize,
+    writing: bool,
+}
+
+/// Writes service output and stepn's own messages during `run`. Producers
+/// queue finished lines and a dedicated thread writes them out, so a slow
+/// terminal never blocks the service tasks (unless the policy says so).
+pub struct Writer {
+    policy: Overflow,
+    state: Mutex<State>,
+    queued: Condvar,
+    drained: Condvar,
+    /// Free slots for `Overflow::Block`.
+    space: Semaphore,
+    /// Discard everything instead of writing it, while something else (the
+    /// `--tui` dashboard) owns the terminal.
+    muted: AtomicBool,
+}
+
+impl Writer {
+    pub fn spawn(policy: Overflow) -> Arc<Self> {
+        let writer = Arc::new(Writer {
+            policy,
+            state: Mutex::new(State::default()),
+            queued: Condvar::new(),
+            drained: Condvar::new(),
+            space: Semaphore::new(BUFFER_LINES),
+            muted: AtomicBool::new(false),
+        });
+        let thread_writer = Arc::clone(&writer);
+        std::thread::spawn(move || thread_writer.write_loop());
+        writer
+    }
+
+    pub fn policy(&self) -> Overflow {
+        self.policy
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::SeqCst);
+    }
+
+    fn write_loop(&self) {
+        loop {
+            let batch = {
+                let mut state = self.state.lock().expect("lock poisoned");
+                while state.records.is_empty() {
+                    state = self.queued.wait(state).expect("lock poisoned");
+                }
+                state.writing = true;
+                state.counted = 0;
+                std::mem::take(&mut state.records)
+            };
+
+            let counted = batch.iter().filter(|r| r.counted).count();
+            let batch = if self.muted.load(Ordering::SeqCst) {
+                VecDeque::new()
+            } else {
+                batch
+            };
+            // Write errors (e.g. a closed pipe) are ignored so that services
+            // keep running and their output keeps being drained.
+            let mut stdout = std::io::stdout().lock();
+            let mut stderr = std::io::stderr().lock();
+            for record in batch {
+                let _ = match record.target {
+                    Target::Stdout => writeln!(stdout, "{}", record.text),
+                    Target::Stderr => writeln!(stderr, "{}", record.text),
+                };
+            }
+            let _ = stdout.flush();
+            drop((stdout, stderr));
+
+            if self.policy == Overflow::Block {
+                self.space.add_permits(counted);
+            }
+            let mut state = self.state.lock().expect("lock poisoned");
+            state.writing = false;
+            if state.records.is_empty() {
+                self.drained.notify_all();
+            }
+        }
+    }
+
+    fn enqueue(&self, state: &mut State, record: Record) {
+        if self.muted.load(Ordering::SeqCst) {
+            if record.counted && self.policy == Overflow::Block {
+                self.space.add_permits(1);
+            }
+            return;
+        }
+        if record.counted {
+            state.counted += 1;
+        }
+        state.records.push_back(record);
+        self.queued.notify_one();
+    }
+
+    /// Queues one of stepn's own messages, regardless of the policy.
+    pub fn push(&self, target: Target, text: String) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let record = Record {
+            target,
+            text,
+            counted: false,
+        };
+        self.enqueue(&mut state, record);
+    }
+
+    /// Queues a service line according to the policy. Returns false if the
+    /// line was discarded.
+    pub async fn push_line(&self, target: Target, text: String) -> bool {
+        let record = Record {
+            target,
+            text,
+            counted: true,
+        };
+        match self.policy {
+            Overflow::Block => {
+                self.space
+                    .acquire()
+                    .await
+                    .expect("semaphore is never closed")
+                    .forget();
+                let mut state = self.state.lock().expect("lock poisoned");
+                self.enqueue(&mut state, record);
+                true
+            }
+            Overflow::DropOldest => {
+                let mut state = self.state.lock().expect("lock poisoned");
+                if state.counted >= BUFFER_LINES {
+                    if let Some(i) = state.records.iter().position(|r| r.counted) {
+                        state.records.remove(i);
+                        state.counted -= 1;
+                    }
+                }
+                self.enqueue(&mut state, record);
+                true
+            }
+            Overflow::Summarise => {
+                let mut state = self.state.lock().expect("lock poisoned");
+                if state.counted >= BUFFER_LINES {
+                    return false;
+                }
+                self.enqueue(&mut state, record);
+                true
+            }
+        }
+    }
+
+    /// Whether a service line would currently be queued without waiting or
+    /// dropping anything.
+    pub fn has_room(&self) -> bool {
+        self.state.lock().expect("lock poisoned").counted < BUFFER_LINES
+    }
+
+    /// Blocks until everything queued so far has been written.
+    pub fn flush(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        while !state.records.is_empty() || state.writing {
+            state = self.drained.wait(state).expect("lock poisoned");
+        }
+    }
+}